@@ -0,0 +1,101 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rayon::prelude::*;
+use spatial_hash::{CellAlign, CoordinateKind, SpatialHash};
+
+const FREQ: usize = 1024;
+
+fn points() -> Vec<(f32, f32, usize)> {
+    (0..FREQ * FREQ)
+        .map(|i| {
+            let x = (i % FREQ) as f32 / FREQ as f32;
+            let y = (i / FREQ) as f32 / FREQ as f32;
+            (x, y, i)
+        })
+        .collect()
+}
+
+fn build_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("Bench Build");
+    group.sample_size(10);
+    let kind = CoordinateKind::Cube {
+        side_len: 0.01,
+        align: CellAlign::Corner,
+    };
+    let pts = points();
+    group.bench_function("serial", |b| {
+        b.iter(|| SpatialHash::from_iter_with(kind, black_box(&pts).iter().copied()).len())
+    });
+    group.bench_function("parallel", |b| {
+        b.iter(|| SpatialHash::par_build(kind, black_box(&pts).par_iter().copied()).len())
+    });
+    group.finish()
+}
+
+fn batch_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("Bench Batch");
+    group.sample_size(10);
+    let kind = CoordinateKind::Cube {
+        side_len: 0.01,
+        align: CellAlign::Corner,
+    };
+    // visit the grid in a scattered order, since sorted input is already cheap to insert
+    let pts = points();
+    let n = pts.len();
+    let pts = (0..n).map(|i| pts[(i * 7919) % n]).collect::<Vec<_>>();
+    group.bench_function("add", |b| {
+        b.iter(|| {
+            let mut sh = SpatialHash::new(kind);
+            for &(x, y, i) in black_box(&pts) {
+                sh.add(x, y, i);
+            }
+            sh.len()
+        })
+    });
+    group.bench_function("add_batch", |b| {
+        b.iter(|| {
+            let mut sh = SpatialHash::new(kind);
+            sh.add_batch(black_box(&pts).iter().copied());
+            sh.len()
+        })
+    });
+    group.finish()
+}
+
+fn capacity_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("Bench Capacity");
+    group.sample_size(10);
+    // roughly a hundred points land in each cell
+    let kind = CoordinateKind::Cube {
+        side_len: 0.01,
+        align: CellAlign::Corner,
+    };
+    let pts = points();
+    group.bench_function("add", |b| {
+        b.iter(|| {
+            let mut sh = SpatialHash::new(kind);
+            for &(x, y, i) in black_box(&pts) {
+                sh.add(x, y, i);
+            }
+            sh.len()
+        })
+    });
+    group.bench_function("add_with_capacity_hint", |b| {
+        b.iter(|| {
+            let mut sh = SpatialHash::new(kind);
+            for &(x, y, i) in black_box(&pts) {
+                sh.add_with_capacity_hint(x, y, i, 128);
+            }
+            sh.len()
+        })
+    });
+    group.finish()
+}
+
+criterion_group!(
+    benches,
+    build_benchmark,
+    batch_benchmark,
+    capacity_benchmark
+);
+
+criterion_main!(benches);
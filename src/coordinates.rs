@@ -5,9 +5,10 @@ fn sqr(v: f32) -> f32 {
     v * v
 }
 
-/// distance between two points
-fn dist_sqr([x, y]: [f32; 2], [a, b]: [f32; 2]) -> f32 {
-    sqr(x - a) + sqr(y - b)
+/// The number of integers in `lo..=hi`.
+#[inline]
+pub(crate) fn span_len(lo: i32, hi: i32) -> u64 {
+    (hi as i64 - lo as i64 + 1).max(0) as u64
 }
 
 /// A coordinate on a regular grid.
@@ -17,28 +18,74 @@ pub trait RegularCoord: Hash {
     fn from_euclidean(x: f32, y: f32, param: f32) -> Self;
     fn to_euclidean(&self, param: f32) -> [f32; 2];
 
+    /// The area covered by one cell of a grid with the given parameter.
+    fn cell_area(param: f32) -> f32
+    where
+        Self: Sized;
+
     fn one_ring(&self) -> [Self; Self::NEIGHBORS]
     where
         Self: Sized;
 
     /// A specialized function for performing clipping on neighbors if they do not need to be
-    /// checked.
-    fn one_ring_clipped(&self, x: f32, y: f32, param: f32) -> impl Iterator<Item = Self>
+    /// checked, because no point in them is within `radius` of (x,y).
+    /// The returned iterator does not borrow `self`.
+    fn one_ring_clipped(
+        &self,
+        x: f32,
+        y: f32,
+        param: f32,
+        radius: f32,
+    ) -> impl Iterator<Item = Self> + use<Self>
     where
         Self: Sized,
         [Self; Self::NEIGHBORS]:,
     {
-        let _ = (x, y, param);
+        let _ = (x, y, param, radius);
         self.one_ring().into_iter()
     }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct HexAxial<T> {
     pub q: T,
     pub r: T,
 }
 
+/// Which way up hexagons are laid out.
+///
+/// Flat top hexagons are pointy top ones mirrored across the line `y = x`, with `q` and `r`
+/// swapped, so `x = 1.5 * q * R` and neighbors keep the same axial offsets.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum HexOrientation {
+    /// A corner at the top and bottom, with rows of hexagons along the x-axis.
+    #[default]
+    PointyTop,
+    /// An edge at the top and bottom, with columns of hexagons along the y-axis.
+    FlatTop,
+}
+
+impl HexOrientation {
+    /// Maps a point between world space and the frame in which hexagons are pointy top.
+    /// This is its own inverse.
+    pub(crate) fn flip(self, [x, y]: [f32; 2]) -> [f32; 2] {
+        match self {
+            HexOrientation::PointyTop => [x, y],
+            HexOrientation::FlatTop => [y, x],
+        }
+    }
+    /// Maps a hexagon between this orientation and the pointy top one. This is its own
+    /// inverse.
+    pub(crate) fn flip_hex(self, h: HexAxial<i32>) -> HexAxial<i32> {
+        match self {
+            HexOrientation::PointyTop => h,
+            HexOrientation::FlatTop => HexAxial { q: h.r, r: h.q },
+        }
+    }
+}
+
 impl Hash for HexAxial<i32> {
     fn hash<H: Hasher>(&self, state: &mut H) {
         state.write_i32(self.q);
@@ -65,6 +112,9 @@ impl HexAxial<f32> {
         Euclidean { x, y }
     }
 
+    /// Rounds to the hex whose center is nearest. Each cube coordinate is rounded separately,
+    /// then the one which moved the most is recovered from the other two so that
+    /// `q + r + s == 0` still holds.
     pub fn round(&self) -> HexAxial<i32> {
         let q = self.q.round();
         let r = self.r.round();
@@ -102,9 +152,35 @@ impl RegularCoord for HexAxial<i32> {
     fn from_euclidean(x: f32, y: f32, circumradius: f32) -> Self {
         HexAxial::<f32>::new(x, y, circumradius).round()
     }
+    fn cell_area(circumradius: f32) -> f32 {
+        1.5 * (3.0f32).sqrt() * sqr(circumradius)
+    }
+    /// Keeps neighbors unless (x,y) is further than `radius` outside one of the three pairs of
+    /// parallel edges of that neighbor, which is a lower bound on the distance to it.
+    fn one_ring_clipped(
+        &self,
+        x: f32,
+        y: f32,
+        circumradius: f32,
+        radius: f32,
+    ) -> impl Iterator<Item = Self> + use<> {
+        let root3: f32 = (3.0f32).sqrt();
+        let apothem = circumradius * root3 / 2.;
+        self.one_ring().into_iter().filter(move |n| {
+            let [cx, cy] = n.to_euclidean(circumradius);
+            let [dx, dy] = [x - cx, y - cy];
+            [[1., 0.], [0.5, root3 / 2.], [-0.5, root3 / 2.]]
+                .into_iter()
+                .all(|[nx, ny]| (dx * nx + dy * ny).abs() - apothem <= radius)
+        })
+    }
     fn to_euclidean(&self, circumradius: f32) -> [f32; 2] {
-        let _ = circumradius;
-        todo!()
+        let root3: f32 = (3.0f32).sqrt();
+        let q = self.q as f32;
+        let r = self.r as f32;
+        let x = (root3 * q + r * root3 / 2.0) * circumradius;
+        let y = 1.5 * r * circumradius;
+        [x, y]
     }
 }
 
@@ -115,15 +191,114 @@ impl HexAxial<i32> {
     fn neighbor_indices() -> [[i32; 2]; 6] {
         [[1, 0], [1, -1], [0, -1], [-1, 0], [-1, 1], [0, 1]]
     }
+    /// Every hexagon which may have a point within `rad` of (x,y), each once, along with an
+    /// upper bound on how many there are. Such a hexagon has its center within
+    /// `rad + circumradius` of (x,y), which bounds `r`, and then `q` within each row.
+    pub(crate) fn near(
+        x: f32,
+        y: f32,
+        rad: f32,
+        circumradius: f32,
+    ) -> (u64, impl Iterator<Item = Self>) {
+        let root3: f32 = (3.0f32).sqrt();
+        let d = rad + circumradius;
+        let row_h = 1.5 * circumradius;
+        let col_w = root3 * circumradius;
+        let r_lo = ((y - d) / row_h).floor() as i32;
+        let r_hi = ((y + d) / row_h).ceil() as i32;
+        let cols = move |r: i32| {
+            let shift = r as f32 / 2.;
+            ((x - d) / col_w - shift).floor() as i32..=((x + d) / col_w - shift).ceil() as i32
+        };
+        let width = ((2. * d / col_w).ceil() as u64).saturating_add(2);
+        let count = span_len(r_lo, r_hi).saturating_mul(width);
+        let hexes = (r_lo..=r_hi).flat_map(move |r| cols(r).map(move |q| HexAxial { q, r }));
+        (count, hexes)
+    }
+    /// The hexagon containing (x,y) when hexagons are laid out with the given orientation.
+    pub fn from_euclidean_oriented(
+        x: f32,
+        y: f32,
+        circumradius: f32,
+        orientation: HexOrientation,
+    ) -> Self {
+        let [x, y] = orientation.flip([x, y]);
+        orientation.flip_hex(Self::from_euclidean(x, y, circumradius))
+    }
+    /// The center of this hexagon when hexagons are laid out with the given orientation.
+    pub fn to_euclidean_oriented(
+        &self,
+        circumradius: f32,
+        orientation: HexOrientation,
+    ) -> [f32; 2] {
+        orientation.flip(orientation.flip_hex(*self).to_euclidean(circumradius))
+    }
+    /// Exact point-in-hexagon test for the hexagon drawn around this cell's center.
+    pub fn contains(&self, [x, y]: [f32; 2], circumradius: f32) -> bool {
+        let root3: f32 = (3.0f32).sqrt();
+        let [cx, cy] = self.to_euclidean(circumradius);
+        let [dx, dy] = [x - cx, y - cy];
+        let apothem = circumradius * root3 / 2.;
+        // Pointy top hexagons have edge normals every 60 degrees starting from the x-axis.
+        [[1., 0.], [0.5, root3 / 2.], [-0.5, root3 / 2.]]
+            .into_iter()
+            .all(|[nx, ny]| (dx * nx + dy * ny).abs() <= apothem)
+    }
+    /// The corners of this hexagon, counter-clockwise starting from the right of the top.
+    pub fn vertices(&self, circumradius: f32) -> [[f32; 2]; 6] {
+        let [cx, cy] = self.to_euclidean(circumradius);
+        [0, 1, 2, 3, 4, 5].map(|i| {
+            let a = std::f32::consts::FRAC_PI_6 + i as f32 * std::f32::consts::FRAC_PI_3;
+            [cx + circumradius * a.cos(), cy + circumradius * a.sin()]
+        })
+    }
+    /// The midpoint of the edge shared with each neighbor, in the same order as `one_ring`.
+    /// Since all hexagons are the same size, this is halfway between the two centers.
+    pub fn edge_midpoints(&self, circumradius: f32) -> [[f32; 2]; 6] {
+        let [cx, cy] = self.to_euclidean(circumradius);
+        self.one_ring().map(|n| {
+            let [nx, ny] = n.to_euclidean(circumradius);
+            [(cx + nx) / 2., (cy + ny) / 2.]
+        })
+    }
     pub fn offset(self, dq: i32, dr: i32) -> HexAxial<i32> {
         HexAxial {
             q: self.q + dq,
             r: self.r + dr,
         }
     }
+    /// Number of steps between neighbors needed to get from this hex to `o`.
+    pub fn distance(&self, o: &Self) -> i32 {
+        let [dq, dr] = [self.q - o.q, self.r - o.r];
+        (dq.abs() + dr.abs() + (dq + dr).abs()) / 2
+    }
+    /// The `6 * radius` cells exactly `radius` steps from this hex, walking once around it, or
+    /// just this hex if `radius` is 0.
+    pub fn ring(self, radius: u32) -> impl Iterator<Item = HexAxial<i32>> {
+        let r = radius as i32;
+        let dirs = Self::neighbor_indices();
+        // walk each side of the ring in the direction of one neighbor each, starting from the
+        // corner in the direction of the neighbor before the first side.
+        let mut corner = self.offset(-r, r);
+        let corners = dirs.map(|[dq, dr]| {
+            let c = corner;
+            corner = corner.offset(dq * r, dr * r);
+            c
+        });
+        let sides = (0..6).flat_map(move |i| {
+            let [dq, dr] = dirs[i];
+            (0..r).map(move |j| corners[i].offset(dq * j, dr * j))
+        });
+        (radius == 0).then_some(self).into_iter().chain(sides)
+    }
+    /// All cells within `radius` steps of this hex, one ring at a time from the center out.
+    pub fn spiral(self, radius: u32) -> impl Iterator<Item = HexAxial<i32>> {
+        (0..=radius).flat_map(move |r| self.ring(r))
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Euclidean<T> {
     pub x: T,
     pub y: T,
@@ -144,7 +319,67 @@ impl Euclidean<f32> {
     }
 }
 
+/// Where the cells of a grid of squares or rectangles are placed relative to the origin.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CellAlign {
+    /// Cell `[0, 0]` spans `[0, w) x [0, h)`, so lattice points are on the corners of cells.
+    #[default]
+    Corner,
+    /// Cell `[0, 0]` spans `[-w/2, w/2) x [-h/2, h/2)`, so lattice points are the centers of
+    /// cells.
+    Center,
+}
+
+impl CellAlign {
+    /// The lower corner of cell `[0, 0]` on a grid of `w` by `h` rectangles.
+    pub fn origin(self, w: f32, h: f32) -> [f32; 2] {
+        match self {
+            CellAlign::Corner => [0., 0.],
+            CellAlign::Center => [-w / 2., -h / 2.],
+        }
+    }
+}
+
 impl Euclidean<i32> {
+    /// The cell containing (x,y) on a grid of `w` by `h` rectangles placed by `align`.
+    pub fn from_euclidean_aligned(x: f32, y: f32, w: f32, h: f32, align: CellAlign) -> Self {
+        let [ox, oy] = align.origin(w, h);
+        Self::from_euclidean_rect(x - ox, y - oy, w, h)
+    }
+    /// The lower corner of this cell on a grid of `w` by `h` rectangles placed by `align`.
+    pub fn to_euclidean_aligned(&self, w: f32, h: f32, align: CellAlign) -> [f32; 2] {
+        let [x, y] = self.to_euclidean_rect(w, h);
+        let [ox, oy] = align.origin(w, h);
+        [x + ox, y + oy]
+    }
+    /// The cell containing (x,y) on a grid of `w` by `h` rectangles.
+    pub fn from_euclidean_rect(x: f32, y: f32, w: f32, h: f32) -> Self {
+        Euclidean { x: x / w, y: y / h }.round()
+    }
+    /// The lower corner of this cell on a grid of `w` by `h` rectangles.
+    pub fn to_euclidean_rect(&self, w: f32, h: f32) -> [f32; 2] {
+        [self.x as f32 * w, self.y as f32 * h]
+    }
+    /// Same as `one_ring_clipped`, on a grid of `w` by `h` rectangles.
+    pub fn one_ring_clipped_rect(
+        &self,
+        x: f32,
+        y: f32,
+        w: f32,
+        h: f32,
+        radius: f32,
+    ) -> impl Iterator<Item = Self> {
+        let r2 = radius * radius;
+        // distance along one axis from v to the cell spanning [c, c+1) * len
+        let axis_dist = move |v: f32, c: i32, len: f32| {
+            let lo = c as f32 * len;
+            (lo - v).max(v - (lo + len)).max(0.)
+        };
+        self.one_ring()
+            .into_iter()
+            .filter(move |n| sqr(axis_dist(x, n.x, w)) + sqr(axis_dist(y, n.y, h)) <= r2)
+    }
     fn neighbor_indices() -> [[i32; 2]; 8] {
         [
             [-1, -1],
@@ -179,6 +414,9 @@ impl RegularCoord for Euclidean<i32> {
         let &Euclidean { x, y } = self;
         [x as f32 * side_len, y as f32 * side_len]
     }
+    fn cell_area(side_len: f32) -> f32 {
+        sqr(side_len)
+    }
     const NEIGHBORS: usize = 8;
     fn one_ring(&self) -> [Euclidean<i32>; 8] {
         Self::neighbor_indices().map(move |[dx, dy]| Euclidean {
@@ -187,56 +425,100 @@ impl RegularCoord for Euclidean<i32> {
         })
     }
 
-    fn one_ring_clipped(&self, x: f32, y: f32, side_len: f32) -> impl Iterator<Item = Self> {
-        let sx = self.x as f32 * side_len;
-        let sy = self.y as f32 * side_len;
-        let tl = [sx, sy];
-        let tr = [sx + side_len, sy];
-        let bl = [sx, sy + side_len];
-        let br = [sx + side_len, sy + side_len];
-        let s2 = side_len * side_len;
-        let sx = self.x;
-        let sy = self.y;
-        [
-            (Some(bl), [-1, -1]),
-            (None, [-1, 0]),
-            (Some(tl), [-1, 1]),
-            //
-            (None, [0, -1]),
-            (None, [0, 1]),
-            //
-            (Some(br), [1, -1]),
-            (None, [1, 0]),
-            (Some(tr), [1, 1]),
-        ]
-        .into_iter()
-        .filter_map(move |(pt, [dx, dy])| match pt {
-            None => Some(Euclidean {
-                x: sx + dx,
-                y: sy + dy,
-            }),
-            Some(near) => {
-                if dist_sqr(near, [x, y]) < s2 {
-                    Some(Euclidean {
-                        x: sx + dx,
-                        y: sy + dy,
-                    })
-                } else {
-                    None
+    fn one_ring_clipped(
+        &self,
+        x: f32,
+        y: f32,
+        side_len: f32,
+        radius: f32,
+    ) -> impl Iterator<Item = Self> + use<> {
+        self.one_ring_clipped_rect(x, y, side_len, side_len, radius)
+    }
+}
+
+/// A cube on a 3D grid. This is not a `RegularCoord`, since those are 2D.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Euclidean3<T> {
+    pub x: T,
+    pub y: T,
+    pub z: T,
+}
+
+impl Hash for Euclidean3<i32> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_i32(self.x);
+        state.write_i32(self.y);
+        state.write_i32(self.z);
+    }
+}
+
+impl Euclidean3<i32> {
+    pub const NEIGHBORS: usize = 26;
+    pub fn from_euclidean(x: f32, y: f32, z: f32, side_len: f32) -> Self {
+        Euclidean3 {
+            x: (x / side_len).floor() as i32,
+            y: (y / side_len).floor() as i32,
+            z: (z / side_len).floor() as i32,
+        }
+    }
+    #[inline]
+    pub fn to_euclidean(&self, side_len: f32) -> [f32; 3] {
+        [self.x, self.y, self.z].map(|v| v as f32 * side_len)
+    }
+    pub fn one_ring(&self) -> [Euclidean3<i32>; 26] {
+        let mut out = [*self; 26];
+        let mut i = 0;
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                for dz in -1..=1 {
+                    if dx == 0 && dy == 0 && dz == 0 {
+                        continue;
+                    }
+                    out[i] = self.offset(dx, dy, dz);
+                    i += 1;
                 }
             }
-        })
-        //
+        }
+        out
+    }
+    pub fn offset(self, dx: i32, dy: i32, dz: i32) -> Euclidean3<i32> {
+        Euclidean3 {
+            x: self.x.saturating_add(dx),
+            y: self.y.saturating_add(dy),
+            z: self.z.saturating_add(dz),
+        }
     }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TriCoord<T> {
     pub s: T,
     pub t: T,
     pub u: T,
 }
 
+/// Returned by `TriCoord::try_new` for a point which rounded to no triangle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TriCoordError {
+    /// The malformed coordinate, whose components do not sum to 1 or 2.
+    pub coord: TriCoord<i32>,
+}
+
+impl std::fmt::Display for TriCoordError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let TriCoord { s, t, u } = self.coord;
+        write!(
+            f,
+            "triangle coordinate ({s}, {t}, {u}) sums to {}, expected 1 or 2",
+            s + t + u
+        )
+    }
+}
+
+impl std::error::Error for TriCoordError {}
+
 impl Hash for TriCoord<i32> {
     fn hash<H: Hasher>(&self, state: &mut H) {
         let [x, y] = self.canon2d();
@@ -255,22 +537,109 @@ impl TriCoord<i32> {
     pub fn points_up(&self) -> bool {
         self.s + self.t + self.u == 2
     }
-    pub fn new(x: f32, y: f32, side_len: f32) -> Self {
+    /// The center of this triangle, the same as `to_euclidean`.
+    pub fn centroid(&self, side_len: f32) -> [f32; 2] {
+        self.to_euclidean(side_len)
+    }
+    /// The corners of this triangle, starting with the one opposite its horizontal edge
+    /// followed by the left and right ends of that edge.
+    pub fn vertices(&self, side_len: f32) -> [[f32; 2]; 3] {
+        let [cx, cy] = self.centroid(side_len);
+        let root3: f32 = (3.0f32).sqrt();
+        // the centroid is a third of the way from each edge to the opposite corner.
+        let out = side_len / root3;
+        let h = if self.points_up() { -1. } else { 1. };
+        [
+            [cx, cy - h * out],
+            [cx - side_len / 2., cy + h * out / 2.],
+            [cx + side_len / 2., cy + h * out / 2.],
+        ]
+    }
+    /// The smallest and largest `canon2d` coordinates of any triangle which may have a point
+    /// within `rad` of (x,y). Each raw barycentric value changes by at most
+    /// `2 * rad / (sqrt(3) * side_len)` within `rad`, which bounds both `s` and `t`.
+    pub(crate) fn canon2d_bounds(x: f32, y: f32, rad: f32, side_len: f32) -> ([i32; 2], [i32; 2]) {
+        let root3: f32 = (3.0f32).sqrt();
+        let k = 2. * rad / (root3 * side_len);
+        let [s_raw, t_raw, _] = Self::raw(x, y, side_len);
+        // rounded as in `round_raw`, saturating for huge radii.
+        let s = |v: f32| v.ceil() as i32;
+        let t = |v: f32| (v.floor() as i32).saturating_add(1);
+        let [s_lo, s_hi] = [s(s_raw - k), s(s_raw + k)];
+        (
+            [s_lo.saturating_mul(2), t(t_raw - k)],
+            [s_hi.saturating_mul(2).saturating_add(1), t(t_raw + k)],
+        )
+    }
+    /// The raw barycentric values of (x,y), which are rounded to get the coordinate.
+    fn raw(x: f32, y: f32, side_len: f32) -> [f32; 3] {
         let root3: f32 = (3.0f32).sqrt();
-
         let yr3d3 = y * root3 / 3.;
-        let s = ((x - yr3d3) / side_len).ceil() as i32;
-        let t = ((y * root3 * 2. / 3.) / side_len).floor();
-        let t = t as i32 + 1;
-        let u = ((-x - yr3d3) / side_len).ceil() as i32;
-        let sum = s + t + u;
+        [
+            (x - yr3d3) / side_len,
+            (y * root3 * 2. / 3.) / side_len,
+            (-x - yr3d3) / side_len,
+        ]
+    }
+    fn round_raw([s, t, u]: [f32; 3]) -> Self {
+        Self {
+            s: s.ceil() as i32,
+            t: t.floor() as i32 + 1,
+            u: u.ceil() as i32,
+        }
+    }
+    pub fn new(x: f32, y: f32, side_len: f32) -> Self {
+        let out = Self::round_raw(Self::raw(x, y, side_len));
+        let sum = out.s + out.t + out.u;
 
         debug_assert!(
             sum == 1 || sum == 2,
-            "Internal error, unexpected {sum} {s} {t} {u} {x} {y}"
+            "Internal error, unexpected {sum} {} {} {} {x} {y}",
+            out.s,
+            out.t,
+            out.u,
         );
 
-        Self { s, t, u }
+        out
+    }
+    /// Same as `new`, but returns an error instead of a malformed coordinate when rounding
+    /// error puts (x,y) on no triangle.
+    pub fn try_new(x: f32, y: f32, side_len: f32) -> Result<Self, TriCoordError> {
+        let coord = Self::round_raw(Self::raw(x, y, side_len));
+        match coord.s + coord.t + coord.u {
+            1 | 2 => Ok(coord),
+            _ => Err(TriCoordError { coord }),
+        }
+    }
+    /// Same as `new`, but always returns a valid coordinate. When rounding error puts (x,y) on
+    /// no triangle, the component which was closest to rounding the other way is moved, which
+    /// picks one of the triangles touching (x,y).
+    pub fn new_clamped(x: f32, y: f32, side_len: f32) -> Self {
+        let raw = Self::raw(x, y, side_len);
+        let TriCoord { s, t, u } = Self::round_raw(raw);
+        let mut c = [s, t, u];
+        loop {
+            let sum = c.iter().sum::<i32>();
+            if sum == 1 || sum == 2 {
+                let [s, t, u] = c;
+                return TriCoord { s, t, u };
+            }
+            // how far each raw value is from rounding one step up or down.
+            let (i, _) = c
+                .iter()
+                .zip(raw)
+                .map(|(&c, r)| {
+                    if sum < 1 {
+                        c as f32 - r
+                    } else {
+                        r - (c - 1) as f32
+                    }
+                })
+                .enumerate()
+                .min_by(|(_, a), (_, b)| a.total_cmp(b))
+                .unwrap();
+            c[i] += if sum < 1 { 1 } else { -1 };
+        }
     }
     pub fn canon2d(&self) -> [i32; 2] {
         let sum = self.s + self.t + self.u;
@@ -278,6 +647,16 @@ impl TriCoord<i32> {
         let x = 2 * self.s + if sum == 1 { 0 } else { 1 };
         [x, self.t]
     }
+    /// Inverse of `canon2d`.
+    pub fn from_canon2d([x, t]: [i32; 2]) -> Self {
+        let s = x.div_euclid(2);
+        let sum = 1 + x.rem_euclid(2);
+        TriCoord {
+            s,
+            t,
+            u: sum - s - t,
+        }
+    }
     fn neighbor_indices(up: bool) -> [[i32; 3]; 12] {
         if up {
             [
@@ -317,6 +696,35 @@ impl TriCoord<i32> {
             ]
         }
     }
+    /// Number of steps between neighbors in `one_ring` needed to get from this triangle to
+    /// `o`. Every neighbor differs by at most one in each of `s`, `t` and `u`, and every
+    /// triangle with that property is a neighbor, so this is the largest difference.
+    pub fn distance(&self, o: &Self) -> i32 {
+        let Self { s, t, u } = *self;
+        (s - o.s).abs().max((t - o.t).abs()).max((u - o.u).abs())
+    }
+    /// The `12 * radius` triangles exactly `radius` steps from this one, or just this triangle
+    /// if `radius` is 0. Both orientations of triangle are yielded, since each step may flip
+    /// the orientation.
+    pub fn ring(self, radius: u32) -> impl Iterator<Item = TriCoord<i32>> {
+        let r = radius as i32;
+        let sum = self.s + self.t + self.u;
+        (-r..=r)
+            .flat_map(move |ds| (-r..=r).map(move |dt| [ds, dt]))
+            .flat_map(move |[ds, dt]| {
+                // `u` is picked so that s + t + u is 1 or 2 again, for a down or up triangle.
+                [1, 2].map(|target| TriCoord {
+                    s: self.s + ds,
+                    t: self.t + dt,
+                    u: self.u + target - sum - ds - dt,
+                })
+            })
+            .filter(move |c| self.distance(c) == r)
+    }
+    /// All triangles within `radius` steps of this one, one ring at a time from the center out.
+    pub fn spiral(self, radius: u32) -> impl Iterator<Item = TriCoord<i32>> {
+        (0..=radius).flat_map(move |r| self.ring(r))
+    }
 }
 
 impl RegularCoord for TriCoord<i32> {
@@ -324,8 +732,33 @@ impl RegularCoord for TriCoord<i32> {
     fn from_euclidean(x: f32, y: f32, side_len: f32) -> Self {
         Self::new(x, y, side_len)
     }
-    fn to_euclidean(&self, _side_len: f32) -> [f32; 2] {
-        todo!()
+    fn cell_area(side_len: f32) -> f32 {
+        (3.0f32).sqrt() / 4. * sqr(side_len)
+    }
+    /// Keeps neighbors unless their centroid is further than `radius` plus the circumradius of a
+    /// triangle from (x,y), which is a lower bound on the distance to them that is cheaper to
+    /// check than each edge.
+    fn one_ring_clipped(
+        &self,
+        x: f32,
+        y: f32,
+        side_len: f32,
+        radius: f32,
+    ) -> impl Iterator<Item = Self> + use<> {
+        let root3: f32 = (3.0f32).sqrt();
+        let r2 = sqr(radius + side_len / root3);
+        self.one_ring().into_iter().filter(move |n| {
+            let [cx, cy] = n.to_euclidean(side_len);
+            sqr(cx - x) + sqr(cy - y) <= r2
+        })
+    }
+    /// The centroid of this triangle.
+    fn to_euclidean(&self, side_len: f32) -> [f32; 2] {
+        let root3: f32 = (3.0f32).sqrt();
+        let [s, t, u] = [self.s, self.t, self.u].map(|v| v as f32);
+        let x = 0.5 * (s - u);
+        let y = root3 / 3. * t - root3 / 6. * (s + u);
+        [x * side_len, y * side_len]
     }
     fn one_ring(&self) -> [Self; Self::NEIGHBORS] {
         Self::neighbor_indices(self.points_up()).map(|[ds, dt, du]| TriCoord {
@@ -335,3 +768,32 @@ impl RegularCoord for TriCoord<i32> {
         })
     }
 }
+
+/// The center of the square cell with the given key, which is `[x, y]` of its `Euclidean`
+/// coordinate.
+pub fn euclidean_cell_center([x, y]: [i32; 2], side_len: f32) -> [f32; 2] {
+    let [x, y] = Euclidean { x, y }.to_euclidean(side_len);
+    [x + side_len / 2., y + side_len / 2.]
+}
+
+/// The center of the hex with the given key, which is `[q, r]` of its `HexAxial` coordinate.
+pub fn hex_to_euclidean_center([q, r]: [i32; 2], circumradius: f32) -> [f32; 2] {
+    HexAxial { q, r }.to_euclidean(circumradius)
+}
+
+/// The center of a pointy top hex, on a grid whose hex `(0, 0)` is centered at `origin`.
+pub fn hex_to_pixel(hex: HexAxial<i32>, [ox, oy]: [f32; 2], circumradius: f32) -> [f32; 2] {
+    let [x, y] = hex.to_euclidean(circumradius);
+    [x + ox, y + oy]
+}
+
+/// The pointy top hex containing `pixel`, on a grid whose hex `(0, 0)` is centered at
+/// `origin`.
+pub fn pixel_to_hex([x, y]: [f32; 2], [ox, oy]: [f32; 2], circumradius: f32) -> HexAxial<i32> {
+    HexAxial::from_euclidean(x - ox, y - oy, circumradius)
+}
+
+/// The centroid of the triangle with the given key, which is its `TriCoord::canon2d`.
+pub fn tri_to_euclidean_center(key: [i32; 2], side_len: f32) -> [f32; 2] {
+    TriCoord::from_canon2d(key).to_euclidean(side_len)
+}
@@ -1,25 +1,86 @@
+//! A fast hasher for the small integer keys used by spatial hashes.
+//!
+//! Keys are only a couple of `i32`s, so a general purpose byte hasher such as `RandomState`
+//! spends most of its time on setup and finalization. `SimpleHashBuilder` is the recommended
+//! hasher for this crate when keys are not attacker controlled:
+//!
+//! ```
+//! use spatial_hash::{hash::SimpleHashBuilder, SpatialHash};
+//! let mut sh = SpatialHash::cube(0.1).with_hasher(SimpleHashBuilder::default());
+//! sh.add(0.5, 0.5, ());
+//! assert_eq!(sh.query(0.5, 0.5).len(), 1);
+//! ```
+
 use std::hash::{BuildHasherDefault, Hasher};
-use std::mem::transmute;
 
+/// Mixes each written word into the state FxHash style, then runs a full avalanche on
+/// `finish`, since buckets are picked from the low bits of the hash.
+///
+/// The result only depends on the values written, and not on the pointer width or
+/// endianness of the target: integers are widened to `u64` by value, and bytes passed to
+/// `write` are read as little endian words. The same keys are therefore put in the same
+/// buckets on every platform.
 #[derive(Default)]
 pub struct SimpleHash {
     state: u64,
-    count: usize,
 }
-const MS: [i64; 3] = [1597334677, 3812015801, 2489301273];
+
+const K: u64 = 0x517c_c1b7_2722_0a95;
+
+impl SimpleHash {
+    #[inline]
+    fn add_word(&mut self, w: u64) {
+        self.state = (self.state.rotate_left(5) ^ w).wrapping_mul(K);
+    }
+}
 
 impl Hasher for SimpleHash {
-    fn write(&mut self, _bytes: &[u8]) {
-        unreachable!();
+    fn write(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(8) {
+            let mut word = [0; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.add_word(u64::from_le_bytes(word));
+        }
+    }
+    // the default implementations of these pass native endian bytes to `write`.
+    #[inline]
+    fn write_u8(&mut self, v: u8) {
+        self.add_word(v as u64);
+    }
+    #[inline]
+    fn write_u16(&mut self, v: u16) {
+        self.add_word(v as u64);
+    }
+    #[inline]
+    fn write_u128(&mut self, v: u128) {
+        self.add_word(v as u64);
+        self.add_word((v >> 64) as u64);
     }
     #[inline]
     fn write_i32(&mut self, v: i32) {
-        self.state ^= unsafe { transmute::<i64, u64>((v as i64) * MS[self.count]) };
-        self.count += 1;
+        self.add_word(v as u32 as u64);
+    }
+    #[inline]
+    fn write_u32(&mut self, v: u32) {
+        self.add_word(v as u64);
+    }
+    #[inline]
+    fn write_u64(&mut self, v: u64) {
+        self.add_word(v);
+    }
+    #[inline]
+    fn write_usize(&mut self, v: usize) {
+        self.add_word(v as u64);
     }
+    /// The murmur3 64 bit finalizer.
     #[inline]
     fn finish(&self) -> u64 {
-        self.state
+        let mut h = self.state;
+        h ^= h >> 33;
+        h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+        h ^= h >> 33;
+        h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+        h ^ (h >> 33)
     }
 }
 
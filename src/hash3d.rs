@@ -0,0 +1,114 @@
+use crate::coordinates::Euclidean3;
+use crate::{empty_buckets, fold_hash, DefaultHashBuilder};
+use std::collections::BTreeMap;
+use std::hash::BuildHasher;
+use std::iter;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CoordinateKind3 {
+    Cube { side_len: f32 },
+}
+
+/// A 3D Spatial Hash, which has to store keys separately from the 2D spatial hash since they
+/// have an extra dimension.
+#[derive(Debug, Clone)]
+pub struct SpatialHash3<T, const N: usize = 256, S = DefaultHashBuilder> {
+    /// Where the items are actually stored
+    data: [BTreeMap<[i32; 3], Vec<T>>; N],
+
+    /// Hash State
+    state: S,
+
+    pub kind: CoordinateKind3,
+}
+
+impl<T> SpatialHash3<T, 256, DefaultHashBuilder> {
+    /// Create an empty 3D spatial hash
+    pub fn new(kind: CoordinateKind3) -> Self {
+        Self::with_kind(kind)
+    }
+    pub fn cube(side_len: f32) -> Self {
+        Self::new(CoordinateKind3::Cube { side_len })
+    }
+}
+
+impl<T, const N: usize, S: Default> SpatialHash3<T, N, S> {
+    /// Create an empty 3D spatial hash with any number of buckets and hasher.
+    pub fn with_kind(kind: CoordinateKind3) -> Self {
+        SpatialHash3 {
+            data: empty_buckets(),
+            kind,
+            state: Default::default(),
+        }
+    }
+}
+
+impl<T, const N: usize, S> SpatialHash3<T, N, S> {
+    pub fn with_hasher(self, state: S) -> Self {
+        SpatialHash3 { state, ..self }
+    }
+
+    /// Remove all items from this spatial hash.
+    pub fn clear(&mut self) {
+        for d in &mut self.data {
+            d.clear()
+        }
+    }
+
+    /// Total number of items stored in this spatial hash.
+    pub fn len(&self) -> usize {
+        self.data
+            .iter()
+            .flat_map(|bins| bins.values())
+            .map(Vec::len)
+            .sum()
+    }
+
+    /// Returns true if there are no items in this spatial hash.
+    pub fn is_empty(&self) -> bool {
+        self.data
+            .iter()
+            .flat_map(|bins| bins.values())
+            .all(Vec::is_empty)
+    }
+}
+
+impl<T, const N: usize, S: BuildHasher + Default> SpatialHash3<T, N, S> {
+    fn coord(&self, x: f32, y: f32, z: f32) -> Euclidean3<i32> {
+        let CoordinateKind3::Cube { side_len } = self.kind;
+        Euclidean3::from_euclidean(x, y, z, side_len)
+    }
+    pub fn idx(&self, x: f32, y: f32, z: f32) -> (usize, [i32; 3]) {
+        let ec = self.coord(x, y, z);
+        (self.coord_idx(ec), [ec.x, ec.y, ec.z])
+    }
+    #[inline]
+    pub fn coord_idx(&self, ec: Euclidean3<i32>) -> usize {
+        fold_hash::<N>(self.state.hash_one(ec))
+    }
+
+    /// Adds an item to this spatial hash. Returns the item set that it was added to.
+    pub fn add(&mut self, x: f32, y: f32, z: f32, t: T) -> &mut [T] {
+        let (idx, key) = self.idx(x, y, z);
+        let v = self.data[idx].entry(key).or_default();
+        v.push(t);
+        v
+    }
+
+    pub fn query(&self, x: f32, y: f32, z: f32) -> &[T] {
+        let (idx, key) = self.idx(x, y, z);
+        self.data[idx].get(&key).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// Query items in the cube containing (x,y,z) and its 26 neighbors, starting with the
+    /// cube containing (x,y,z).
+    pub fn query_one_ring(&self, x: f32, y: f32, z: f32) -> impl Iterator<Item = &[T]> + '_ {
+        let ec = self.coord(x, y, z);
+        iter::once(ec).chain(ec.one_ring()).filter_map(|c| {
+            self.data[self.coord_idx(c)]
+                .get(&[c.x, c.y, c.z])
+                .map(Vec::as_slice)
+        })
+    }
+}
@@ -1,30 +1,263 @@
 #![feature(generic_const_exprs)]
+#![feature(impl_trait_in_assoc_type)]
 #![allow(incomplete_features)]
-#![feature(generic_arg_infer)]
-#![feature(return_position_impl_trait_in_trait)]
 
 pub mod coordinates;
 pub mod hash;
 pub mod lines;
 
+mod hash3d;
+pub use hash3d::{CoordinateKind3, SpatialHash3};
+
+pub mod storage;
+use storage::BinMap;
+
+#[cfg(feature = "serde")]
+mod serialize;
+
 #[cfg(test)]
 mod tests;
 
-use coordinates::{Euclidean, HexAxial, RegularCoord, TriCoord};
+use coordinates::{
+    euclidean_cell_center, span_len, tri_to_euclidean_center, Euclidean, HexAxial, RegularCoord,
+    TriCoord,
+};
+pub use coordinates::{CellAlign, HexOrientation};
 use std::collections::hash_map::RandomState;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 use std::default::Default;
-use std::hash::{BuildHasher, Hasher};
+use std::hash::BuildHasher;
 use std::iter;
+use std::marker::PhantomData;
+use std::mem::size_of;
 
 type DefaultHashBuilder = RandomState;
 //type DefaultHashBuilder = hash::SimpleHashBuilder;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CoordinateKind {
-    Cube { side_len: f32 },
-    Hex { circumradius: f32 },
-    Tri { side_len: f32 },
+    Cube {
+        side_len: f32,
+        #[cfg_attr(feature = "serde", serde(default))]
+        align: CellAlign,
+    },
+    /// Axis aligned rectangles of width `w` and height `h`, for data which is spread much
+    /// further along one axis than the other. Neighbors are the same as for cubes.
+    Rect {
+        w: f32,
+        h: f32,
+        #[cfg_attr(feature = "serde", serde(default))]
+        align: CellAlign,
+    },
+    Hex {
+        circumradius: f32,
+        #[cfg_attr(feature = "serde", serde(default))]
+        orientation: HexOrientation,
+    },
+    Tri {
+        side_len: f32,
+    },
+}
+
+impl CoordinateKind {
+    /// Key of the cell containing (x,y).
+    fn key(&self, x: f32, y: f32) -> [i32; 2] {
+        match *self {
+            CoordinateKind::Cube { side_len, align } => {
+                let ec = Euclidean::from_euclidean_aligned(x, y, side_len, side_len, align);
+                [ec.x, ec.y]
+            }
+            CoordinateKind::Rect { w, h, align } => {
+                let ec = Euclidean::from_euclidean_aligned(x, y, w, h, align);
+                [ec.x, ec.y]
+            }
+            CoordinateKind::Tri { side_len } => TriCoord::from_euclidean(x, y, side_len).canon2d(),
+            CoordinateKind::Hex {
+                circumradius,
+                orientation,
+            } => {
+                let ec = HexAxial::from_euclidean_oriented(x, y, circumradius, orientation);
+                [ec.q, ec.r]
+            }
+        }
+    }
+    /// The position reported for the cell with the given key by `iter`: the lower corner of
+    /// cubes, and the center of hexes and triangles.
+    fn position(&self, [u, v]: [i32; 2]) -> [f32; 2] {
+        match *self {
+            CoordinateKind::Cube { side_len, align } => {
+                Euclidean { x: u, y: v }.to_euclidean_aligned(side_len, side_len, align)
+            }
+            CoordinateKind::Rect { w, h, align } => {
+                Euclidean { x: u, y: v }.to_euclidean_aligned(w, h, align)
+            }
+            CoordinateKind::Tri { side_len } => {
+                TriCoord::from_canon2d([u, v]).to_euclidean(side_len)
+            }
+            CoordinateKind::Hex {
+                circumradius,
+                orientation,
+            } => HexAxial { q: u, r: v }.to_euclidean_oriented(circumradius, orientation),
+        }
+    }
+    /// The corners of the cell with the given key.
+    #[cfg(feature = "rand")]
+    fn vertices(&self, [u, v]: [i32; 2]) -> Vec<[f32; 2]> {
+        match *self {
+            CoordinateKind::Cube { side_len, .. } => {
+                let [x, y] = self.position([u, v]);
+                let [hx, hy] = [x + side_len, y + side_len];
+                vec![[x, y], [hx, y], [hx, hy], [x, hy]]
+            }
+            CoordinateKind::Rect { w, h, .. } => {
+                let [x, y] = self.position([u, v]);
+                vec![[x, y], [x + w, y], [x + w, y + h], [x, y + h]]
+            }
+            CoordinateKind::Tri { side_len } => {
+                TriCoord::from_canon2d([u, v]).vertices(side_len).to_vec()
+            }
+            CoordinateKind::Hex {
+                circumradius,
+                orientation,
+            } => orientation
+                .flip_hex(HexAxial { q: u, r: v })
+                .vertices(circumradius)
+                .map(|p| orientation.flip(p))
+                .to_vec(),
+        }
+    }
+    /// The center of the cell with the given key.
+    fn centroid(&self, key: [i32; 2]) -> [f32; 2] {
+        match *self {
+            CoordinateKind::Cube { side_len, align } => {
+                let [cx, cy] = euclidean_cell_center(key, side_len);
+                let [ox, oy] = align.origin(side_len, side_len);
+                [cx + ox, cy + oy]
+            }
+            CoordinateKind::Rect { w, h, .. } => {
+                let [x, y] = self.position(key);
+                [x + w / 2., y + h / 2.]
+            }
+            CoordinateKind::Tri { side_len } => tri_to_euclidean_center(key, side_len),
+            CoordinateKind::Hex {
+                circumradius,
+                orientation,
+            } => HexAxial {
+                q: key[0],
+                r: key[1],
+            }
+            .to_euclidean_oriented(circumradius, orientation),
+        }
+    }
+    /// The axis aligned bounding box of the cell with the given key, as its min and max corners.
+    fn bounds(&self, key: [i32; 2]) -> ([f32; 2], [f32; 2]) {
+        let root3: f32 = (3.0f32).sqrt();
+        let [cx, cy] = self.centroid(key);
+        let [hw, below, above] = match *self {
+            // exact corners, so that neighboring cells share their edges without a gap.
+            CoordinateKind::Cube { .. } | CoordinateKind::Rect { .. } => {
+                return (self.position(key), self.position([key[0] + 1, key[1] + 1]));
+            }
+            // pointy top hexagons have corners above and below the center but edges to the
+            // sides, and flat top ones the reverse.
+            CoordinateKind::Hex {
+                circumradius,
+                orientation,
+            } => {
+                let apothem = circumradius * root3 / 2.;
+                match orientation {
+                    HexOrientation::PointyTop => [apothem, circumradius, circumradius],
+                    HexOrientation::FlatTop => [circumradius, apothem, apothem],
+                }
+            }
+            // the centroid is a third of the way from the flat edge to the opposite corner.
+            CoordinateKind::Tri { side_len } => {
+                let out = side_len / root3;
+                if TriCoord::from_canon2d(key).points_up() {
+                    [side_len / 2., out / 2., out]
+                } else {
+                    [side_len / 2., out, out / 2.]
+                }
+            }
+        };
+        ([cx - hw, cy - below], [cx + hw, cy + above])
+    }
+    /// The number of cells across a world of the given size which wraps around. Errors unless
+    /// this is a grid of cubes or rectangles which fits a whole number of times, and at least
+    /// 3 times so that neighbors across the edge are distinct cells.
+    fn wrap_cells(&self, size: [f32; 2]) -> Result<[i32; 2], String> {
+        let Some((cell, _)) = self.axis_aligned() else {
+            return Err(format!("only cubes and rectangles can wrap, not {self:?}"));
+        };
+        let mut cells = [0; 2];
+        for i in 0..2 {
+            let n = size[i] / cell[i];
+            let whole = n.round();
+            if (n - whole).abs() >= 1e-3 || whole < 3. {
+                return Err(format!(
+                    "a wrapping world must be a whole number of at least 3 cells across, not {n}"
+                ));
+            }
+            cells[i] = whole as i32;
+        }
+        Ok(cells)
+    }
+    /// The width and height of each cell and the lower corner of cell `[0, 0]` for cubes and
+    /// rectangles, or `None` for kinds whose cells are not axis aligned rectangles.
+    fn axis_aligned(&self) -> Option<([f32; 2], [f32; 2])> {
+        match *self {
+            CoordinateKind::Cube { side_len, .. } => Some(([side_len; 2], self.grid_origin())),
+            CoordinateKind::Rect { w, h, .. } => Some(([w, h], self.grid_origin())),
+            CoordinateKind::Hex { .. } | CoordinateKind::Tri { .. } => None,
+        }
+    }
+    /// The lower corner of cell `[0, 0]` for cubes and rectangles, and the origin otherwise.
+    fn grid_origin(&self) -> [f32; 2] {
+        match *self {
+            CoordinateKind::Cube { side_len, align } => align.origin(side_len, side_len),
+            CoordinateKind::Rect { w, h, align } => align.origin(w, h),
+            CoordinateKind::Hex { .. } | CoordinateKind::Tri { .. } => [0., 0.],
+        }
+    }
+    /// A lower bound on the distance from any point in a cell to any cell which is more than
+    /// `rings` neighbor steps away from it.
+    fn ring_clearance(&self, rings: u32) -> f32 {
+        let rings = rings as f32;
+        let root3: f32 = (3.0f32).sqrt();
+        match *self {
+            CoordinateKind::Cube { side_len, .. } => rings * side_len,
+            CoordinateKind::Rect { w, h, .. } => rings * w.min(h),
+            // The outline of the first `rings` rings zigzags, and its inner corners are
+            // (1.5 * rings + 0.5) * circumradius from the center, in the same direction as a
+            // corner of the center cell.
+            CoordinateKind::Hex { circumradius, .. } => (1.5 * rings - 0.5) * circumradius,
+            // Each ring adds at least a full row of triangles in every direction.
+            CoordinateKind::Tri { side_len } => rings * side_len * root3 / 2.,
+        }
+    }
+}
+
+/// `.` for an empty cell, the count if there are fewer than 10 items, or `#` otherwise.
+fn occupancy_char(n: usize) -> char {
+    match n {
+        0 => '.',
+        1..=9 => char::from_digit(n as u32, 10).unwrap(),
+        _ => '#',
+    }
+}
+
+/// Cell sizes `(cube_side, hex_circumradius, tri_side)` for which the inscribed circle of
+/// every kind of cell has the given radius, so that the three kinds can be compared fairly.
+///
+/// The inradius of each cell is its distance from the center to the middle of an edge:
+/// - A square of side `l` has inradius `l / 2`, so `l = 2r`.
+/// - A hexagon of circumradius `R` has inradius `R * sqrt(3) / 2`, so `R = 2r / sqrt(3)`.
+/// - An equilateral triangle of side `l` has height `l * sqrt(3) / 2`, and its centroid is a
+///   third of the way up, so `r = l / (2 * sqrt(3))` and `l = 2 * sqrt(3) * r`.
+pub fn matched_params(radius: f32) -> (f32, f32, f32) {
+    let root3: f32 = (3.0f32).sqrt();
+    (2. * radius, 2. * radius / root3, 2. * root3 * radius)
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -49,17 +282,97 @@ impl<I, S: Iterator<Item = I>, T: Iterator<Item = I>, U: Iterator<Item = I>> Ite
     }
 }
 
+/// How the hash of a cell is mapped to one of the `N` buckets.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Fold {
+    /// The hash modulo `N`, which is a mask when `N` is a power of two. For other `N` this
+    /// needs a division, and buckets below `2^64 % N` are slightly more likely.
+    #[default]
+    Modulo,
+    /// `(hash * N) >> 64`, which maps the high bits of the hash uniformly onto `0..N` without
+    /// dividing, for any `N`.
+    MultiplyShift,
+}
+
+impl Fold {
+    #[inline]
+    fn apply<const N: usize>(self, hash: u64) -> usize {
+        match self {
+            Fold::Modulo => fold_hash::<N>(hash),
+            Fold::MultiplyShift => fold_multiply_shift::<N>(hash),
+        }
+    }
+}
+
+/// A world which wraps around, along with the number of cells across it, which is checked
+/// once when it is set rather than on each lookup.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Wrap {
+    size: [f32; 2],
+    cells: [i32; 2],
+}
+
+impl Wrap {
+    /// Panics unless `kind` can wrap with the given size, as described in
+    /// `SpatialHash::with_wrap`.
+    fn new(kind: &CoordinateKind, size: [f32; 2]) -> Self {
+        match kind.wrap_cells(size) {
+            Ok(cells) => Wrap { size, cells },
+            Err(msg) => panic!("{msg}"),
+        }
+    }
+}
+
+/// Moves `key` onto a world which wraps around after `cells` cells in each direction.
+#[inline]
+fn wrapped([u, v]: [i32; 2], cells: Option<[i32; 2]>) -> [i32; 2] {
+    match cells {
+        None => [u, v],
+        Some([cx, cy]) => [u.rem_euclid(cx), v.rem_euclid(cy)],
+    }
+}
+
+/// `N` empty bin maps for the buckets of a `SpatialHash`.
+fn empty_buckets<B: Default, const N: usize>() -> [B; N] {
+    assert!(N > 0, "a SpatialHash needs at least one bucket, but N is 0");
+    [(); N].map(|_| B::default())
+}
+
 /// A Hexagonal Spatial Hash.
 /// Unlike most spatial hashes that use cubes, this uses hexagons.
+///
+/// `N` is the number of buckets, and must be at least 1. Creating one with `N == 0` panics.
+/// With `N == 1` keys are never hashed, which suits bin maps such as the `HashMap` of
+/// `FlatSpatialHash` that do their own hashing.
 #[derive(Debug, Clone)]
-pub struct SpatialHash<T, const N: usize = 256, S = DefaultHashBuilder> {
-    /// Where the items are actually stored
-    data: [BTreeMap<[i32; 2], Vec<T>>; N],
+pub struct SpatialHash<
+    T,
+    const N: usize = 256,
+    S = DefaultHashBuilder,
+    B = BTreeMap<[i32; 2], Vec<T>>,
+> {
+    /// Where the items are actually stored, split into `N` buckets by the hash of each key.
+    data: [B; N],
 
     /// Hash State
     state: S,
 
+    /// The grid used to bin items. Changing this directly leaves existing items under keys
+    /// for the old grid, and a wrapping world with its old number of cells, use `rebuild_from`
+    /// to move them as well.
     pub kind: CoordinateKind,
+
+    /// How hashes are mapped to buckets, changed with `with_fold`.
+    fold: Fold,
+
+    /// The world if it wraps around, set with `with_wrap`.
+    wrap: Option<Wrap>,
+
+    /// The width and height of a world which does not wrap, set with `with_world_bounds`.
+    world_bounds: Option<[f32; 2]>,
+
+    _items: PhantomData<T>,
 }
 
 impl<T> Default for SpatialHash<T, 256, DefaultHashBuilder> {
@@ -71,14 +384,13 @@ impl<T> Default for SpatialHash<T, 256, DefaultHashBuilder> {
 impl<T> SpatialHash<T, 256, DefaultHashBuilder> {
     /// Create an empty hex spatial hash
     pub fn new(kind: CoordinateKind) -> Self {
-        SpatialHash {
-            data: [(); _].map(|_| BTreeMap::new()),
-            kind,
-            state: Default::default(),
-        }
+        Self::with_kind(kind)
     }
     pub fn cube(side_len: f32) -> Self {
-        Self::new(CoordinateKind::Cube { side_len })
+        Self::new(CoordinateKind::Cube {
+            side_len,
+            align: CellAlign::Corner,
+        })
     }
     /// Primary use case.
     /// Height should be equivalent to query radius.
@@ -87,220 +399,1929 @@ impl<T> SpatialHash<T, 256, DefaultHashBuilder> {
         Self::new(CoordinateKind::Tri { side_len })
     }
     pub fn hex(circumradius: f32) -> Self {
-        Self::new(CoordinateKind::Hex { circumradius })
+        Self::new(CoordinateKind::Hex {
+            circumradius,
+            orientation: HexOrientation::PointyTop,
+        })
+    }
+    /// Creates a spatial hash containing each (x, y, item) in `iter`.
+    pub fn from_iter_with(
+        kind: CoordinateKind,
+        iter: impl IntoIterator<Item = (f32, f32, T)>,
+    ) -> Self {
+        let mut sh = Self::new(kind);
+        sh.extend(iter);
+        sh
+    }
+    /// Creates a spatial hash from values on a regular lattice, with each `values[i][j]` at
+    /// `origin + [i * step[0], j * step[1]]`.
+    pub fn from_grid(
+        kind: CoordinateKind,
+        origin: [f32; 2],
+        step: [f32; 2],
+        values: &[&[T]],
+    ) -> Self
+    where
+        T: Clone,
+    {
+        let [ox, oy] = origin;
+        let [sx, sy] = step;
+        Self::from_iter_with(
+            kind,
+            values.iter().enumerate().flat_map(|(i, col)| {
+                col.iter()
+                    .enumerate()
+                    .map(move |(j, v)| (ox + i as f32 * sx, oy + j as f32 * sy, v.clone()))
+            }),
+        )
     }
 }
 
-impl<T, const N: usize, S> SpatialHash<T, N, S> {
-    /// Create an empty hex spatial hash
-    pub fn with_hasher(self, state: S) -> Self {
-        SpatialHash { state, ..self }
+impl<T> SpatialHash<T, 256, hash::SimpleHashBuilder> {
+    /// Create an empty spatial hash which uses `hash::SimpleHashBuilder`, so the same items
+    /// are always put in the same buckets, unlike the randomly seeded default hasher. Useful
+    /// for reproducible benchmarks and snapshot tests.
+    pub fn deterministic(kind: CoordinateKind) -> Self {
+        Self::with_kind(kind)
     }
+}
 
-    /// Remove all items from this spatial hash.
-    pub fn clear(&mut self) {
-        for d in &mut self.data {
-            d.clear()
+impl<T, const N: usize, S: Default, B: BinMap<T>> SpatialHash<T, N, S, B> {
+    /// Create an empty spatial hash with any number of buckets, hasher and bin map, such as
+    /// `SpatialHash::<T, 1024>::with_kind(kind)`.
+    pub fn with_kind(kind: CoordinateKind) -> Self {
+        SpatialHash {
+            data: empty_buckets(),
+            kind,
+            state: Default::default(),
+            fold: Fold::default(),
+            wrap: None,
+            world_bounds: None,
+            _items: PhantomData,
         }
     }
+    /// Same as `cube`, for any number of buckets.
+    pub fn with_cube(side_len: f32) -> Self {
+        Self::with_kind(CoordinateKind::Cube {
+            side_len,
+            align: CellAlign::Corner,
+        })
+    }
+    /// Same as `tri_h`, for any number of buckets.
+    pub fn with_tri_h(height: f32) -> Self {
+        let side_len = TriCoord::height_to_side_len(height);
+        Self::with_kind(CoordinateKind::Tri { side_len })
+    }
+    /// Same as `hex`, for any number of buckets.
+    pub fn with_hex(circumradius: f32) -> Self {
+        Self::with_kind(CoordinateKind::Hex {
+            circumradius,
+            orientation: HexOrientation::PointyTop,
+        })
+    }
 }
 
-impl<T, const N: usize, S: BuildHasher + Default> SpatialHash<T, N, S> {
-    pub fn idx(&self, x: f32, y: f32) -> (usize, [i32; 2]) {
-        match self.kind {
-            CoordinateKind::Cube { side_len } => {
-                let ec = Euclidean::from_euclidean(x, y, side_len);
-                (self.coord_idx(ec), [ec.x, ec.y])
-            }
-            CoordinateKind::Tri { side_len } => {
-                let ec = TriCoord::from_euclidean(x, y, side_len);
-                (self.coord_idx(ec), ec.canon2d())
-            }
-            CoordinateKind::Hex { circumradius } => {
-                let ec = HexAxial::from_euclidean(x, y, circumradius);
-                (self.coord_idx(ec), [ec.q, ec.r])
-            }
+/// Configures the kind, bucket count and hasher of a `SpatialHash` before creating it:
+///
+/// ```
+/// use spatial_hash::{hash::SimpleHashBuilder, CoordinateKind, HexOrientation, SpatialHashBuilder};
+/// let mut sh = SpatialHashBuilder::new()
+///     .kind(CoordinateKind::Hex { circumradius: 0.1, orientation: HexOrientation::FlatTop })
+///     .hasher(SimpleHashBuilder::default())
+///     .buckets::<64>()
+///     .build();
+/// sh.add(0.5, 0.5, ());
+/// assert_eq!(sh.query(0.5, 0.5).len(), 1);
+/// ```
+#[derive(Debug, Clone)]
+pub struct SpatialHashBuilder<T, const N: usize = 256, S = DefaultHashBuilder> {
+    kind: CoordinateKind,
+    state: S,
+    fold: Fold,
+    wrap: Option<[f32; 2]>,
+    world_bounds: Option<[f32; 2]>,
+    _items: PhantomData<T>,
+}
+
+impl<T> SpatialHashBuilder<T> {
+    /// Starts from the same configuration as `SpatialHash::default`.
+    pub fn new() -> Self {
+        SpatialHashBuilder {
+            kind: CoordinateKind::Tri { side_len: 1. },
+            state: Default::default(),
+            fold: Fold::default(),
+            wrap: None,
+            world_bounds: None,
+            _items: PhantomData,
         }
     }
-    #[inline]
-    pub fn coord_idx(&self, ax: impl RegularCoord) -> usize {
-        let mut h = self.state.build_hasher();
-        ax.hash(&mut h);
-        (h.finish() as usize) % N
+}
+
+impl<T> Default for SpatialHashBuilder<T> {
+    fn default() -> Self {
+        Self::new()
     }
-    /// Iterates over each bin in this spatial hash, returning the 2D coordinate in floating
-    /// point, and all the stored values.
-    #[inline]
-    pub fn iter(&self) -> impl Iterator<Item = ([f32; 2], &[T])> {
-        self.data.iter().flat_map(|bins| {
-            bins.iter().filter_map(|(&[u, v], vals)| {
-                if vals.is_empty() {
-                    return None;
-                }
-                let coord = match self.kind {
-                    CoordinateKind::Cube { side_len } => {
-                        Euclidean { x: u, y: v }.to_euclidean(side_len)
-                    }
-                    CoordinateKind::Tri { side_len: _ } => {
-                        todo!("TODO convert uv to TriCoord")
-                    }
-                    CoordinateKind::Hex { circumradius } => {
-                        HexAxial { q: u, r: v }.to_euclidean(circumradius)
-                    }
-                };
-                Some((coord, vals.as_slice()))
-            })
-        })
+}
+
+impl<T, const N: usize, S> SpatialHashBuilder<T, N, S> {
+    pub fn kind(self, kind: CoordinateKind) -> Self {
+        SpatialHashBuilder { kind, ..self }
+    }
+    pub fn hasher<S2>(self, state: S2) -> SpatialHashBuilder<T, N, S2> {
+        SpatialHashBuilder {
+            kind: self.kind,
+            state,
+            fold: self.fold,
+            wrap: self.wrap,
+            world_bounds: self.world_bounds,
+            _items: PhantomData,
+        }
+    }
+    /// Sets the number of buckets.
+    pub fn buckets<const M: usize>(self) -> SpatialHashBuilder<T, M, S> {
+        SpatialHashBuilder {
+            kind: self.kind,
+            state: self.state,
+            fold: self.fold,
+            wrap: self.wrap,
+            world_bounds: self.world_bounds,
+            _items: PhantomData,
+        }
+    }
+    pub fn fold(self, fold: Fold) -> Self {
+        SpatialHashBuilder { fold, ..self }
+    }
+    /// Same as `SpatialHash::with_wrap`.
+    pub fn wrap(self, size: [f32; 2]) -> Self {
+        SpatialHashBuilder {
+            wrap: Some(size),
+            ..self
+        }
     }
+    /// Same as `SpatialHash::with_world_bounds`.
+    pub fn world_bounds(self, size: [f32; 2]) -> Self {
+        SpatialHashBuilder {
+            world_bounds: Some(size),
+            ..self
+        }
+    }
+    pub fn build(self) -> SpatialHash<T, N, S> {
+        SpatialHash {
+            data: empty_buckets(),
+            state: self.state,
+            kind: self.kind,
+            fold: self.fold,
+            wrap: self.wrap.map(|size| Wrap::new(&self.kind, size)),
+            world_bounds: self.world_bounds,
+            _items: PhantomData,
+        }
+    }
+}
 
-    /// Adds an item to this spatial hash. Returns the item set that it was added to.
-    /// This can be used to sort the items for later querying.
-    /// Mainly exists so you can have a z buffer in it.
-    pub fn add(&mut self, x: f32, y: f32, t: T) -> &mut [T] {
-        let (idx, key) = self.idx(x, y);
-        let v = self.data[idx].entry(key).or_insert_with(Vec::new);
-        v.push(t);
-        v
+/// A spatial hash backed by a single `HashMap` rather than `N` sharded `BTreeMap`s, which can
+/// have lower constant overhead for dense workloads.
+pub type FlatSpatialHash<T, S = DefaultHashBuilder> =
+    SpatialHash<T, 1, S, HashMap<[i32; 2], Vec<T>>>;
+
+impl<T> FlatSpatialHash<T> {
+    /// Create an empty spatial hash backed by a single `HashMap`.
+    pub fn flat(kind: CoordinateKind) -> Self {
+        Self::with_kind(kind)
     }
+}
 
-    /// Returns if two coordinates fall into the same bin for this spatial hash
-    pub fn same_bin(&self, x: f32, y: f32, a: f32, b: f32) -> bool {
-        self.idx(x, y).1 == self.idx(a, b).1
+/// A spatial hash which stores the position each item was added at alongside it, so that
+/// items can be moved to a new grid exactly. All of the usual queries work, and yield
+/// `([x, y], item)` pairs.
+pub type TrackedSpatialHash<T, const N: usize = 256, S = DefaultHashBuilder> =
+    SpatialHash<([f32; 2], T), N, S>;
+
+impl<T> TrackedSpatialHash<T> {
+    /// Create an empty spatial hash which tracks the position of each item.
+    pub fn tracked(kind: CoordinateKind) -> Self {
+        Self::with_kind(kind)
     }
-    pub fn add_one_ring(&mut self, x: f32, y: f32, t: T, cb: impl Fn(&mut [T]))
+}
+
+impl<T, const N: usize, S: BuildHasher + Default, B: BinMap<([f32; 2], T)>>
+    SpatialHash<([f32; 2], T), N, S, B>
+{
+    /// Same as `add`, storing (x,y) along with the item.
+    pub fn add_tracked(&mut self, x: f32, y: f32, t: T) -> &mut [([f32; 2], T)] {
+        self.add(x, y, ([x, y], t))
+    }
+    /// The items in the bin containing (x,y), along with the position each was added at.
+    pub fn query_positions(&self, x: f32, y: f32) -> &[([f32; 2], T)] {
+        self.query(x, y)
+    }
+    /// Switches to a different coordinate kind, moving every item to the cell containing the
+    /// position it was added at.
+    pub fn set_kind(&mut self, kind: CoordinateKind) {
+        self.rebuild_from(kind, |&(p, _)| p);
+    }
+    /// Same as `rebuild_as`, but puts each item in the cell containing the position it was
+    /// added at, rather than at the center of its old cell.
+    pub fn rebuild_tracked_as(&self, kind: CoordinateKind) -> Self
     where
-        T: Copy,
+        T: Clone,
     {
-        match self.kind {
-            CoordinateKind::Cube { side_len } => {
-                let ax = Euclidean::from_euclidean(x, y, side_len);
-                ax.one_ring()
-                    .into_iter()
-                    .chain(iter::once(ax))
-                    .for_each(move |hax| {
-                        let v = self.data[self.coord_idx(hax)]
-                            .entry([hax.x, hax.y])
-                            .or_insert_with(Vec::new);
-                        v.push(t);
-                        cb(v)
-                    });
-            }
-            CoordinateKind::Tri { side_len } => {
-                let ax = TriCoord::from_euclidean(x, y, side_len);
-                ax.one_ring()
-                    .into_iter()
-                    .chain(iter::once(ax))
-                    .for_each(move |hax| {
-                        let v = self.data[self.coord_idx(hax)]
-                            .entry(hax.canon2d())
-                            .or_insert_with(Vec::new);
-                        v.push(t);
-                        cb(v)
-                    });
-            }
-            CoordinateKind::Hex { circumradius } => {
-                let ax = HexAxial::from_euclidean(x, y, circumradius);
-                ax.one_ring()
-                    .into_iter()
-                    .chain(iter::once(ax))
-                    .for_each(move |hax| {
-                        let v = self.data[self.coord_idx(hax)]
-                            .entry([hax.q, hax.r])
-                            .or_insert_with(Vec::new);
-                        v.push(t);
-                        cb(v)
-                    });
+        let mut out = self.empty_with_kind(kind);
+        for (_, _, vals) in self.bins() {
+            for (p, t) in vals {
+                out.add(p[0], p[1], (*p, t.clone()));
             }
         }
+        out
     }
-    /// Adds an item to this spatial hash
-    pub fn add_with_conflict_resolution(
-        &mut self,
-        x: f32,
-        y: f32,
-        t: T,
-        resolve: impl Fn(T, T) -> T,
-    ) {
-        let (idx, key) = self.idx(x, y);
-        use std::collections::btree_map::Entry;
-        match self.data[idx].entry(key) {
-            Entry::Vacant(v) => {
-                v.insert(vec![t]);
-            }
-            Entry::Occupied(mut o) => {
-                assert_eq!(o.get().len(), 1);
-                let v = o.get_mut();
-                let new = resolve(t, v.pop().unwrap());
-                v.push(new);
-            }
+}
+
+#[cfg(feature = "rayon")]
+impl<T: Send> SpatialHash<T, 256, DefaultHashBuilder> {
+    /// Builds a spatial hash from `points` in parallel.
+    /// Items which share a cell are stored in the same order as in `points`, so this is
+    /// identical to adding each point in order.
+    pub fn par_build(
+        kind: CoordinateKind,
+        points: impl rayon::iter::IntoParallelIterator<Item = (f32, f32, T)>,
+    ) -> Self {
+        use rayon::iter::ParallelIterator;
+        let merged = points
+            .into_par_iter()
+            .fold(BTreeMap::new, |mut bins, (x, y, t)| {
+                bins.entry(kind.key(x, y)).or_insert_with(Vec::new).push(t);
+                bins
+            })
+            .reduce(BTreeMap::new, |mut l: BTreeMap<[i32; 2], Vec<T>>, r| {
+                for (key, vals) in r {
+                    l.bin_or_default(key).extend(vals);
+                }
+                l
+            });
+        let mut sh = Self::new(kind);
+        for (key, vals) in merged {
+            let idx = sh.key_idx(key);
+            sh.data[idx].insert_bin(key, vals);
         }
+        sh
     }
+}
 
-    /// adds a line to the spatial hash using the bresenham algorithm.
-    pub fn add_line_bresenham(&mut self, l_start: [f32; 2], l_end: [f32; 2], t: T)
-    where
-        T: Copy,
-    {
-        let (_, l_start) = self.idx(l_start[0], l_start[1]);
-        let (_, l_end) = self.idx(l_end[0], l_end[1]);
-        for [x, y] in lines::bresenham(l_start, l_end) {
-            let idx = self.coord_idx(Euclidean { x, y });
-            self.data[idx]
-                .entry([x, y])
-                .or_insert_with(Vec::new)
-                .push(t);
+impl<T, const N: usize, S: BuildHasher + Default, B: BinMap<T>> Extend<(f32, f32, T)>
+    for SpatialHash<T, N, S, B>
+{
+    fn extend<I: IntoIterator<Item = (f32, f32, T)>>(&mut self, iter: I) {
+        for (x, y, t) in iter {
+            self.add(x, y, t);
         }
     }
+}
 
-    pub fn query(&self, x: f32, y: f32) -> &[T] {
-        let (idx, key) = self.idx(x, y);
-        self.data[idx].get(&key).map(Vec::as_slice).unwrap_or(&[])
+/// Consumes the hash, yielding every item along with the position of its cell as reported
+/// by `iter`. Each bin is drained as it is reached, so nothing is collected up front.
+impl<T, const N: usize, S, B: BinMap<T>> IntoIterator for SpatialHash<T, N, S, B> {
+    type Item = ([f32; 2], T);
+    type IntoIter = impl Iterator<Item = ([f32; 2], T)>;
+    fn into_iter(self) -> Self::IntoIter {
+        let kind = self.kind;
+        self.data
+            .into_iter()
+            .flat_map(B::into_bins)
+            .flat_map(move |(key, vals)| {
+                let p = kind.position(key);
+                vals.into_iter().map(move |t| (p, t))
+            })
     }
+}
 
-    /// Query items in a close proximity to a given (x,y) coordinate.
-    pub fn query_one_ring(&self, x: f32, y: f32) -> impl Iterator<Item = &[T]> + '_ {
-        match self.kind {
-            CoordinateKind::Cube { side_len } => {
-                let ax = Euclidean::from_euclidean(x, y, side_len);
-                let iter = ax
-                    //.one_ring_clipped(x,y,side_len)
-                    .one_ring()
-                    .into_iter()
-                    .chain(iter::once(ax))
-                    .filter_map(|hax| {
-                        self.data[self.coord_idx(hax)]
-                            .get(&[hax.x, hax.y])
-                            .map(Vec::as_slice)
-                    });
-                Tri::A(iter)
+impl<T, const N: usize, S, B: BinMap<T>> SpatialHash<T, N, S, B> {
+    /// Applies `f` to every item, keeping each in the same cell. Bucket indices only depend
+    /// on the keys, so bins stay in the same bucket and nothing is rehashed. The result is
+    /// backed by the same kind of map, so a `FlatSpatialHash` stays flat.
+    pub fn map<U>(self, mut f: impl FnMut(T) -> U) -> SpatialHash<U, N, S, B::Rebind<U>> {
+        SpatialHash {
+            data: self.data.map(|bins| {
+                let mut out = B::Rebind::<U>::default();
+                for (key, vals) in bins.into_bins() {
+                    out.insert_bin(key, vals.into_iter().map(&mut f).collect());
+                }
+                out
+            }),
+            state: self.state,
+            kind: self.kind,
+            fold: self.fold,
+            wrap: self.wrap,
+            world_bounds: self.world_bounds,
+            _items: PhantomData,
+        }
+    }
+
+    /// Same as `map`, but drops the items for which `f` returns `None`, along with any bins
+    /// which become empty.
+    pub fn retain_map<U>(
+        self,
+        mut f: impl FnMut(T) -> Option<U>,
+    ) -> SpatialHash<U, N, S, B::Rebind<U>> {
+        SpatialHash {
+            data: self.data.map(|bins| {
+                let mut out = B::Rebind::<U>::default();
+                for (key, vals) in bins.into_bins() {
+                    let vals = vals.into_iter().filter_map(&mut f).collect::<Vec<_>>();
+                    if !vals.is_empty() {
+                        out.insert_bin(key, vals);
+                    }
+                }
+                out
+            }),
+            state: self.state,
+            kind: self.kind,
+            fold: self.fold,
+            wrap: self.wrap,
+            world_bounds: self.world_bounds,
+            _items: PhantomData,
+        }
+    }
+
+    /// Switches to a different hasher, such as `hash::SimpleHashBuilder`, moving any existing
+    /// items into the bucket picked by the new hasher.
+    pub fn with_hasher<S2: BuildHasher + Default>(self, state: S2) -> SpatialHash<T, N, S2, B> {
+        let mut out = SpatialHash {
+            data: empty_buckets::<B, N>(),
+            state,
+            kind: self.kind,
+            fold: self.fold,
+            wrap: self.wrap,
+            world_bounds: self.world_bounds,
+            _items: PhantomData,
+        };
+        for (key, vals) in self.data.into_iter().flat_map(B::into_bins) {
+            let idx = out.key_idx(key);
+            out.data[idx].insert_bin(key, vals);
+        }
+        out
+    }
+
+    /// Remove all items from this spatial hash.
+    pub fn clear(&mut self) {
+        for d in &mut self.data {
+            d.clear_bins()
+        }
+    }
+
+    /// Keeps only the items for which `pred` returns true, removing bins which become empty.
+    pub fn retain(&mut self, mut pred: impl FnMut(&T) -> bool) {
+        for bins in &mut self.data {
+            bins.retain_bins(|_, vals| {
+                vals.retain(&mut pred);
+                !vals.is_empty()
+            });
+        }
+    }
+
+    /// Releases the spare capacity of every bin and drops bins which are empty, such as after
+    /// a large `retain` or many calls to `remove`.
+    pub fn shrink_to_fit(&mut self) {
+        for bins in &mut self.data {
+            bins.retain_bins(|_, vals| {
+                vals.shrink_to_fit();
+                !vals.is_empty()
+            });
+        }
+    }
+
+    /// Hints that about `additional` more distinct cells will be inserted, split evenly
+    /// between the buckets. This does nothing for the default `BTreeMap` bins, which can't
+    /// allocate ahead of time, but avoids rehashing for `HashMap` bins.
+    pub fn reserve(&mut self, additional: usize) {
+        let per_bucket = additional.div_ceil(N);
+        for bins in &mut self.data {
+            bins.reserve_bins(per_bucket);
+        }
+    }
+
+    /// Each non-empty bin along with its bucket index and integer key.
+    pub fn bins(&self) -> impl Iterator<Item = (usize, [i32; 2], &[T])> {
+        self.data.iter().enumerate().flat_map(|(idx, bins)| {
+            bins.bins()
+                .filter(|(_, vals)| !vals.is_empty())
+                .map(move |(&key, vals)| (idx, key, vals.as_slice()))
+        })
+    }
+
+    /// The key and number of items of each occupied cell, sorted by key, such as for drawing
+    /// a heatmap.
+    pub fn density_grid(&self) -> Vec<([i32; 2], usize)> {
+        let mut grid = self
+            .bins()
+            .map(|(_, key, vals)| (key, vals.len()))
+            .collect::<Vec<_>>();
+        grid.sort_unstable_by_key(|&(key, _)| key);
+        grid
+    }
+
+    /// A uniformly random point within a random occupied cell, or `None` if the hash is empty.
+    /// Cells are picked with probability proportional to the number of items in them, so
+    /// denser regions receive more samples.
+    #[cfg(feature = "rand")]
+    pub fn sample_in_occupied(&self, rng: &mut impl rand::RngCore) -> Option<[f32; 2]> {
+        use rand::Rng;
+        let len = self.len();
+        if len == 0 {
+            return None;
+        }
+        let mut i = rng.gen_range(0..len);
+        let (_, key, _) = self
+            .bins()
+            .find(|(_, _, vals)| {
+                let found = i < vals.len();
+                i = i.saturating_sub(vals.len());
+                found
+            })
+            .unwrap();
+
+        let [s, t] = [rng.gen::<f32>(), rng.gen::<f32>()];
+        // folds the unit square onto the triangle with corners a, b, c.
+        let in_triangle = |a: [f32; 2], b: [f32; 2], c: [f32; 2]| {
+            let [s, t] = if s + t > 1. { [1. - s, 1. - t] } else { [s, t] };
+            [0, 1].map(|i| a[i] + s * (b[i] - a[i]) + t * (c[i] - a[i]))
+        };
+        let vs = self.kind.vertices(key);
+        Some(match self.kind {
+            CoordinateKind::Cube { side_len, .. } => {
+                [vs[0][0] + s * side_len, vs[0][1] + t * side_len]
             }
-            CoordinateKind::Tri { side_len } => {
-                let ax = TriCoord::from_euclidean(x, y, side_len);
-                let iter = ax
-                    .one_ring()
-                    .into_iter()
-                    .chain(iter::once(ax))
-                    .filter_map(|hax| {
-                        self.data[self.coord_idx(hax)]
-                            .get(&hax.canon2d())
-                            .map(Vec::as_slice)
-                    });
-                Tri::B(iter)
-            }
-            CoordinateKind::Hex { circumradius } => {
-                let ax = HexAxial::from_euclidean(x, y, circumradius);
-                let iter = ax
-                    .one_ring()
-                    .into_iter()
-                    .chain(iter::once(ax))
-                    .filter_map(|hax| {
-                        self.data[self.coord_idx(hax)]
-                            .get(&[hax.q, hax.r])
-                            .map(Vec::as_slice)
-                    });
-                Tri::C(iter)
+            CoordinateKind::Rect { w, h, .. } => [vs[0][0] + s * w, vs[0][1] + t * h],
+            CoordinateKind::Tri { .. } => in_triangle(vs[0], vs[1], vs[2]),
+            // fan out from the center, where every triangle has the same area.
+            CoordinateKind::Hex { .. } => {
+                let i = rng.gen_range(0..6);
+                in_triangle(self.kind.centroid(key), vs[i], vs[(i + 1) % 6])
+            }
+        })
+    }
+
+    /// Total number of items stored in this spatial hash.
+    pub fn len(&self) -> usize {
+        self.data
+            .iter()
+            .flat_map(|bins| bins.bins())
+            .map(|(_, v)| v.len())
+            .sum()
+    }
+
+    /// Returns true if there are no items in this spatial hash.
+    pub fn is_empty(&self) -> bool {
+        self.data
+            .iter()
+            .flat_map(|bins| bins.bins())
+            .all(|(_, v)| v.is_empty())
+    }
+
+    /// Number of occupied cells per bucket.
+    pub fn load_factor(&self) -> f32 {
+        let cells: usize = self.data.iter().map(B::num_bins).sum();
+        cells as f32 / N as f32
+    }
+
+    /// Counts of the bins and items in this spatial hash. A large `max_bin_items` compared to
+    /// the mean suggests that cells are too large for how clustered the items are.
+    pub fn capacity_stats(&self) -> CapacityStats {
+        let mut stats = CapacityStats::default();
+        for (_, v) in self.data.iter().flat_map(|bins| bins.bins()) {
+            if v.is_empty() {
+                stats.empty_bins += 1;
+            } else {
+                stats.occupied_bins += 1;
             }
+            stats.total_items += v.len();
+            stats.max_bin_items = stats.max_bin_items.max(v.len());
+            // zero sized items report a capacity of `usize::MAX`
+            stats.total_capacity = stats.total_capacity.saturating_add(v.capacity());
         }
+        stats
+    }
+
+    /// Approximate breakdown of the memory used by this spatial hash.
+    pub fn memory_report(&self) -> MemoryReport {
+        let mut report = MemoryReport {
+            buckets: N * size_of::<B>(),
+            ..Default::default()
+        };
+        for bins in &self.data {
+            report.map_nodes += bins.node_bytes();
+            for (_, v) in bins.bins() {
+                report.items += v.capacity() * size_of::<T>();
+            }
+        }
+        report
+    }
+
+    /// Approximate number of bytes used by this spatial hash, the total of `memory_report`.
+    pub fn memory_bytes(&self) -> usize {
+        self.memory_report().total()
+    }
+}
+
+/// Each unordered pair of distinct elements of `vals`.
+fn pairs_within<T>(vals: &[T]) -> impl Iterator<Item = (&T, &T)> {
+    vals.iter()
+        .enumerate()
+        .flat_map(move |(i, a)| vals[i + 1..].iter().map(move |b| (a, b)))
+}
+
+/// Maps a hash into `0..N`, masking instead of dividing when `N` is a power of two.
+#[inline]
+fn fold_hash<const N: usize>(hash: u64) -> usize {
+    if N.is_power_of_two() {
+        (hash as usize) & (N - 1)
+    } else {
+        (hash as usize) % N
+    }
+}
+
+/// Maps a hash into `0..N` by taking the high bits of `hash * N`.
+#[inline]
+fn fold_multiply_shift<const N: usize>(hash: u64) -> usize {
+    ((hash as u128 * N as u128) >> 64) as usize
+}
+
+/// Finds a cell `a` in `cells` with a neighbor `b` which does not have `a` as a neighbor.
+#[cfg(debug_assertions)]
+fn find_asymmetric<I: Iterator<Item = [i32; 2]>>(
+    cells: impl Iterator<Item = [i32; 2]>,
+    neighbors: impl Fn([i32; 2]) -> I,
+) -> Option<([i32; 2], [i32; 2])> {
+    for a in cells {
+        for b in neighbors(a) {
+            if !neighbors(b).any(|c| c == a) {
+                return Some((a, b));
+            }
+        }
+    }
+    None
+}
+
+/// The result of `SpatialHash::add_reporting`.
+#[derive(Debug)]
+pub struct AddResult<'a, T> {
+    /// The items in the cell the item was added to, including the new item.
+    pub slice: &'a mut [T],
+    /// True if the cell had no items before insertion.
+    pub was_new_cell: bool,
+    /// Number of items in the cell before insertion.
+    pub prior_count: usize,
+}
+
+/// The occupied cells and items of a spatial hash, stored contiguously so that it is cheap to
+/// take and restore every frame. Created by `SpatialHash::snapshot`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot<T> {
+    kind: CoordinateKind,
+    /// Each occupied cell along with the number of its items in `items`.
+    cells: Vec<([i32; 2], usize)>,
+    items: Vec<T>,
+}
+
+impl<T> Snapshot<T> {
+    /// Number of items in the snapshot.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
+
+/// How full the bins of a spatial hash are.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CapacityStats {
+    /// Bins holding at least one item.
+    pub occupied_bins: usize,
+    /// Bins which are stored but hold no items, such as ones created by `entry`.
+    pub empty_bins: usize,
+    pub total_items: usize,
+    /// The most items in any one bin.
+    pub max_bin_items: usize,
+    /// Sum of the capacity of each bin's `Vec`.
+    pub total_capacity: usize,
+}
+
+impl CapacityStats {
+    /// Average number of items in each occupied bin.
+    pub fn mean_bin_items(&self) -> f32 {
+        if self.occupied_bins == 0 {
+            return 0.;
+        }
+        self.total_items as f32 / self.occupied_bins as f32
+    }
+}
+
+/// Approximate number of bytes used by each part of a spatial hash.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryReport {
+    /// The `N` bucket maps stored inline in the spatial hash.
+    pub buckets: usize,
+    /// Estimated size of the map nodes which hold each bin.
+    pub map_nodes: usize,
+    /// Allocated capacity of each bin's `Vec`.
+    pub items: usize,
+}
+
+impl MemoryReport {
+    pub fn total(&self) -> usize {
+        self.buckets + self.map_nodes + self.items
+    }
+}
+
+/// Queries several spatial hashes sharing one grid as if they were a single hash, such as a
+/// hash of static geometry alongside one of moving entities.
+#[derive(Debug, Clone)]
+pub struct LayeredQuery<
+    'a,
+    T,
+    const N: usize = 256,
+    S = DefaultHashBuilder,
+    B = BTreeMap<[i32; 2], Vec<T>>,
+> {
+    layers: Vec<&'a SpatialHash<T, N, S, B>>,
+}
+
+impl<'a, T, const N: usize, S: BuildHasher + Default, B: BinMap<T>> LayeredQuery<'a, T, N, S, B> {
+    /// Panics if the layers do not all have the same kind.
+    pub fn new(layers: impl IntoIterator<Item = &'a SpatialHash<T, N, S, B>>) -> Self {
+        let layers = layers.into_iter().collect::<Vec<_>>();
+        if let Some(first) = layers.first() {
+            assert!(
+                layers.iter().all(|l| l.kind == first.kind),
+                "All layers must use the same coordinate kind"
+            );
+        }
+        LayeredQuery { layers }
+    }
+    /// Every item in the one ring of (x,y), from each layer in order.
+    pub fn query_one_ring(&self, [x, y]: [f32; 2]) -> impl Iterator<Item = &'a T> + '_ {
+        self.layers
+            .iter()
+            .flat_map(move |l| l.query_one_ring_items(x, y))
+    }
+}
+
+impl<T, const N: usize, S: BuildHasher + Default, B: BinMap<T>> SpatialHash<T, N, S, B> {
+    /// The bucket index and key of the cell containing (x,y).
+    /// (x,y) must be finite: `NaN` is silently put in a cell at the origin, and infinities in
+    /// cells at the edge of the `i32` range, so this panics in debug builds instead. Use
+    /// `try_idx` for points which may not be finite.
+    pub fn idx(&self, x: f32, y: f32) -> (usize, [i32; 2]) {
+        let key = self.key(x, y);
+        (self.key_idx(key), key)
+    }
+    /// Same as `idx`, but returns `None` if x or y is `NaN` or infinite.
+    pub fn try_idx(&self, x: f32, y: f32) -> Option<(usize, [i32; 2])> {
+        (x.is_finite() && y.is_finite()).then(|| self.idx(x, y))
+    }
+    /// Key of the cell containing (x,y), without computing its bucket.
+    #[inline]
+    fn key(&self, x: f32, y: f32) -> [i32; 2] {
+        debug_assert!(
+            x.is_finite() && y.is_finite(),
+            "non-finite coordinate ({x}, {y})"
+        );
+        wrapped(self.kind.key(x, y), self.wrap_cells())
+    }
+    /// The number of cells across the world if it wraps around.
+    #[inline]
+    fn wrap_cells(&self) -> Option<[i32; 2]> {
+        self.wrap.map(|w| w.cells)
+    }
+    /// Distance from (x,y) to `p`, going the shorter way around if the world wraps.
+    fn wrapped_dist(&self, [x, y]: [f32; 2], [a, b]: [f32; 2]) -> f32 {
+        let [mut dx, mut dy] = [(a - x).abs(), (b - y).abs()];
+        if let Some(Wrap { size: [w, h], .. }) = self.wrap {
+            dx = dx.rem_euclid(w).min(w - dx.rem_euclid(w));
+            dy = dy.rem_euclid(h).min(h - dy.rem_euclid(h));
+        }
+        dx.hypot(dy)
+    }
+    /// Index of the bucket holding a precomputed coordinate, which is hashed as is. This
+    /// matches `idx(x, y).0` when `c` is the cell `kind` puts (x,y) in, so it skips converting
+    /// from floating point again. That cell is `Euclidean::from_euclidean_aligned` for cubes
+    /// and rectangles, `HexAxial::from_euclidean_oriented` for hexes and
+    /// `TriCoord::from_euclidean` for triangles. If the world wraps, `c` must already be
+    /// wrapped into it.
+    #[inline]
+    pub fn bin_for_coord<C: RegularCoord>(&self, c: C) -> usize {
+        self.coord_idx(c)
+    }
+    #[inline]
+    pub fn coord_idx(&self, ax: impl RegularCoord) -> usize {
+        // with a single bucket, `data` is laid out as just one bin map and this folds to a
+        // constant, so nothing is hashed and every lookup goes straight to that map.
+        if N == 1 {
+            return 0;
+        }
+        self.fold.apply::<N>(self.state.hash_one(ax))
+    }
+    /// Index of the bucket for a key returned by `idx`.
+    /// Every coordinate kind hashes exactly its key, so this agrees with `coord_idx`.
+    #[inline]
+    fn key_idx(&self, [x, y]: [i32; 2]) -> usize {
+        self.coord_idx(Euclidean { x, y })
+    }
+    #[inline]
+    fn bin(&self, key: [i32; 2]) -> Option<&Vec<T>> {
+        self.data[self.key_idx(key)].get_bin(&key)
+    }
+    /// Keys of the cells neighboring the cell which contains (x,y), excluding that cell, in
+    /// the order of `RegularCoord::one_ring`. Keys are in the same form as those returned by
+    /// `idx`, so triangles use `TriCoord::canon2d`.
+    #[inline]
+    pub fn neighbor_keys(&self, x: f32, y: f32) -> impl Iterator<Item = [i32; 2]> + '_ {
+        self.neighbor_keys_of(self.key(x, y))
+    }
+    /// Keys of the cells neighboring the cell with the given key.
+    fn neighbor_keys_of(&self, [u, v]: [i32; 2]) -> impl Iterator<Item = [i32; 2]> {
+        let cells = self.wrap_cells();
+        match self.kind {
+            CoordinateKind::Cube { .. } | CoordinateKind::Rect { .. } => {
+                let ax = Euclidean { x: u, y: v };
+                Tri::A(
+                    ax.one_ring()
+                        .into_iter()
+                        .map(move |c| wrapped([c.x, c.y], cells)),
+                )
+            }
+            CoordinateKind::Tri { .. } => {
+                let ax = TriCoord::from_canon2d([u, v]);
+                Tri::B(ax.one_ring().into_iter().map(|c| c.canon2d()))
+            }
+            CoordinateKind::Hex { .. } => {
+                let ax = HexAxial { q: u, r: v };
+                Tri::C(ax.one_ring().into_iter().map(|c| [c.q, c.r]))
+            }
+        }
+    }
+    /// Renders the occupancy of the hash within `bounds` (min, max) as `rows` lines of `cols`
+    /// characters, with the first line at the top (max y). Each character samples the cell
+    /// under the center of its screen position, and is `.` if that cell is empty, the number
+    /// of items if there are fewer than 10, or `#` otherwise.
+    pub fn debug_grid(&self, bounds: ([f32; 2], [f32; 2]), cols: usize, rows: usize) -> String {
+        let ([lx, ly], [hx, hy]) = bounds;
+        let w = (hx - lx) / cols as f32;
+        let h = (hy - ly) / rows as f32;
+        let mut out = String::with_capacity((cols + 1) * rows);
+        for row in 0..rows {
+            let y = hy - (row as f32 + 0.5) * h;
+            for col in 0..cols {
+                let x = lx + (col as f32 + 0.5) * w;
+                out.push(occupancy_char(self.query(x, y).len()));
+            }
+            out.push('\n');
+        }
+        out
+    }
+    /// Like `debug_grid`, but with one character per cell key in `[min, max]` inclusive, so
+    /// nothing is missed or sampled twice. The first line is `v = max[1]`, and each line goes
+    /// from `u = min[0]` to `max[0]`. For hexagons and triangles keys are axial and
+    /// `TriCoord::canon2d` coordinates, so the grid is a skewed approximation of the plane.
+    pub fn debug_cells(&self, min: [i32; 2], max: [i32; 2]) -> String {
+        let cols = (max[0] - min[0] + 1).max(0) as usize;
+        let rows = (max[1] - min[1] + 1).max(0) as usize;
+        let mut out = String::with_capacity((cols + 1) * rows);
+        for v in (min[1]..=max[1]).rev() {
+            for u in min[0]..=max[0] {
+                out.push(occupancy_char(self.bin([u, v]).map_or(0, Vec::len)));
+            }
+            out.push('\n');
+        }
+        out
+    }
+    /// Checks that every neighbor of an occupied cell also lists that cell as a neighbor,
+    /// returning the first pair which does not. Asymmetric neighborhoods cause missed
+    /// collisions, so this is intended for debugging new neighbor tables, and only exists in
+    /// debug builds.
+    #[cfg(debug_assertions)]
+    pub fn check_neighbor_symmetry(&self) -> Result<(), ([i32; 2], [i32; 2])> {
+        let occupied = self
+            .data
+            .iter()
+            .flat_map(|bins| bins.bins().map(|(&k, _)| k));
+        match find_asymmetric(occupied, |k| self.neighbor_keys_of(k)) {
+            None => Ok(()),
+            Some(pair) => Err(pair),
+        }
+    }
+    /// Iterates over each bin in this spatial hash, returning the 2D coordinate in floating
+    /// point, and all the stored values.
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = ([f32; 2], &[T])> {
+        self.data.iter().flat_map(|bins| {
+            bins.bins().filter_map(|(&[u, v], vals)| {
+                if vals.is_empty() {
+                    return None;
+                }
+                Some((self.kind.position([u, v]), vals.as_slice()))
+            })
+        })
+    }
+
+    /// Like `iter`, but yields mutable slices so every stored item can be updated in one pass.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = ([f32; 2], &mut [T])> {
+        let kind = self.kind;
+        self.data.iter_mut().flat_map(move |bins| {
+            bins.bins_mut().filter_map(move |(&key, vals)| {
+                if vals.is_empty() {
+                    return None;
+                }
+                Some((kind.position(key), vals.as_mut_slice()))
+            })
+        })
+    }
+
+    /// Removes every item from the hash, yielding each along with the position of its cell as
+    /// reported by `iter`. The hash is empty as soon as this is called, even if the returned
+    /// iterator is dropped early.
+    pub fn drain(&mut self) -> impl Iterator<Item = ([f32; 2], T)> {
+        let kind = self.kind;
+        let data = std::mem::replace(&mut self.data, empty_buckets());
+        data.into_iter()
+            .flat_map(B::into_bins)
+            .flat_map(move |(key, vals)| {
+                let p = kind.position(key);
+                vals.into_iter().map(move |t| (p, t))
+            })
+    }
+
+    /// Copies the occupied cells and their items, so that the hash can later be returned to
+    /// this state with `restore`.
+    pub fn snapshot(&self) -> Snapshot<T>
+    where
+        T: Clone,
+    {
+        let mut snap = Snapshot {
+            kind: self.kind,
+            cells: vec![],
+            items: Vec::with_capacity(self.len()),
+        };
+        for (_, key, vals) in self.bins() {
+            snap.cells.push((key, vals.len()));
+            snap.items.extend_from_slice(vals);
+        }
+        snap
+    }
+
+    /// Returns the hash to the state captured by `snapshot`. Bins which are occupied both now
+    /// and in the snapshot keep their allocation.
+    pub fn restore(&mut self, snap: &Snapshot<T>)
+    where
+        T: Clone,
+    {
+        for bins in &mut self.data {
+            bins.bins_mut().for_each(|(_, vals)| vals.clear());
+        }
+        self.kind = snap.kind;
+        let mut items = snap.items.as_slice();
+        for &(key, n) in &snap.cells {
+            let (vals, rest) = items.split_at(n);
+            items = rest;
+            let idx = self.key_idx(key);
+            self.data[idx].bin_or_default(key).extend_from_slice(vals);
+        }
+        for bins in &mut self.data {
+            bins.retain_bins(|_, vals| !vals.is_empty());
+        }
+    }
+
+    /// Cells which are occupied in this hash but empty in `other`, along with their position
+    /// as reported by `iter`. Both must have the same kind, but may have different hasher
+    /// states. If `other` wraps, each cell is looked up where it lands in `other`'s world.
+    pub fn difference<'a, const M: usize, S2: BuildHasher + Default, B2: BinMap<T>>(
+        &'a self,
+        other: &'a SpatialHash<T, M, S2, B2>,
+    ) -> impl Iterator<Item = ([f32; 2], &'a [T])> {
+        assert_eq!(self.kind, other.kind, "difference requires matching kinds");
+        self.bins()
+            .filter(|&(_, key, _)| {
+                other
+                    .bin(wrapped(key, other.wrap_cells()))
+                    .is_none_or(Vec::is_empty)
+            })
+            .map(|(_, key, vals)| (self.kind.position(key), vals))
+    }
+
+    /// Switches to a different coordinate kind, re-inserting every item at the position
+    /// returned by `pos`. The hash does not keep the original position of each item, so it
+    /// must be recoverable from the item itself. A wrapping world keeps its size, so this
+    /// panics if `kind` cannot wrap around it.
+    pub fn rebuild_from(&mut self, kind: CoordinateKind, pos: impl Fn(&T) -> [f32; 2]) {
+        self.wrap = self.wrap.map(|w| Wrap::new(&kind, w.size));
+        let old = std::mem::replace(&mut self.data, empty_buckets());
+        self.kind = kind;
+        for (_, vals) in old.into_iter().flat_map(B::into_bins) {
+            for t in vals {
+                let [x, y] = pos(&t);
+                self.add(x, y, t);
+            }
+        }
+    }
+
+    /// Clones every item into a new hash of the given kind, placing each at the center of the
+    /// cell it was in. Items move by at most the size of a cell, so this is only exact when
+    /// each item was at the center of its cell. The fold, wrap and world bounds are kept, so
+    /// this panics if the world wraps and `kind` cannot wrap around it.
+    pub fn rebuild_as(&self, kind: CoordinateKind) -> Self
+    where
+        T: Clone,
+    {
+        let mut out = self.empty_with_kind(kind);
+        for (_, key, vals) in self.bins() {
+            let [x, y] = self.kind.centroid(key);
+            out.entry(x, y).extend_from_slice(vals);
+        }
+        out
+    }
+
+    /// An empty hash with the same fold, wrap and world bounds as this one, but the given kind.
+    fn empty_with_kind(&self, kind: CoordinateKind) -> Self {
+        SpatialHash {
+            data: empty_buckets(),
+            state: S::default(),
+            kind,
+            fold: self.fold,
+            wrap: self.wrap.map(|w| Wrap::new(&kind, w.size)),
+            world_bounds: self.world_bounds,
+            _items: PhantomData,
+        }
+    }
+
+    /// Clones every item of `other` into this hash. Both must have the same kind, but may
+    /// have different hasher states. If this hash wraps, cells of `other` outside the world
+    /// are wrapped into it.
+    pub fn extend_from<const M: usize, S2, B2: BinMap<T>>(
+        &mut self,
+        other: &SpatialHash<T, M, S2, B2>,
+    ) where
+        T: Clone,
+    {
+        assert_eq!(self.kind, other.kind, "extend_from requires matching kinds");
+        for (&key, vals) in other.data.iter().flat_map(B2::bins) {
+            let key = wrapped(key, self.wrap_cells());
+            let idx = self.key_idx(key);
+            self.data[idx].bin_or_default(key).extend_from_slice(vals);
+        }
+    }
+
+    /// Moves every item of `other` into this hash, appending to the bins of shared cells.
+    /// Both must have the same kind. Each bin is moved whole, and its bucket is recomputed with
+    /// this hash's state, so `other` may use a different hasher or bucket count. If this hash
+    /// wraps, cells of `other` outside the world are wrapped into it.
+    pub fn merge<const M: usize, S2, B2: BinMap<T>>(&mut self, other: SpatialHash<T, M, S2, B2>) {
+        assert_eq!(self.kind, other.kind, "merge requires matching kinds");
+        for (key, vals) in other.data.into_iter().flat_map(B2::into_bins) {
+            let key = wrapped(key, self.wrap_cells());
+            let idx = self.key_idx(key);
+            let bin = self.data[idx].bin_or_default(key);
+            if bin.is_empty() {
+                *bin = vals;
+            } else {
+                bin.extend(vals);
+            }
+        }
+    }
+
+    /// Moves all items into a spatial hash with `M` buckets, keeping the hasher state.
+    pub fn resize_buckets<const M: usize>(self) -> SpatialHash<T, M, S, B> {
+        let mut out = SpatialHash {
+            data: empty_buckets::<B, M>(),
+            state: self.state,
+            kind: self.kind,
+            fold: self.fold,
+            wrap: self.wrap,
+            world_bounds: self.world_bounds,
+            _items: PhantomData,
+        };
+        for (key, vals) in self.data.into_iter().flat_map(B::into_bins) {
+            let idx = out.key_idx(key);
+            out.data[idx].insert_bin(key, vals);
+        }
+        out
+    }
+
+    /// Switches how hashes are mapped to buckets, moving any existing items into their new
+    /// bucket.
+    pub fn with_fold(mut self, fold: Fold) -> Self {
+        if fold == self.fold {
+            return self;
+        }
+        let old = std::mem::replace(&mut self.data, empty_buckets());
+        self.fold = fold;
+        for (key, vals) in old.into_iter().flat_map(B::into_bins) {
+            let idx = self.key_idx(key);
+            self.data[idx].insert_bin(key, vals);
+        }
+        self
+    }
+
+    /// How hashes are mapped to buckets.
+    pub fn fold(&self) -> Fold {
+        self.fold
+    }
+
+    /// Makes the world wrap around with the given width and height, or stops wrapping for
+    /// `None`. Cells on opposite edges then neighbor each other, and points outside of the
+    /// world are binned as if moved back into it, so existing items are moved into their
+    /// wrapped cells. Only cubes and rectangles can wrap, and the size must be a whole number
+    /// of at least 3 cells across. Distances computed from item positions, such as in
+    /// `k_nearest`, are not wrapped.
+    pub fn with_wrap(mut self, size: Option<[f32; 2]>) -> Self {
+        self.wrap = size.map(|size| Wrap::new(&self.kind, size));
+        let cells = self.wrap_cells();
+        let old = std::mem::replace(&mut self.data, empty_buckets());
+        for (key, vals) in old.into_iter().flat_map(B::into_bins) {
+            let key = wrapped(key, cells);
+            let idx = self.key_idx(key);
+            let bin = self.data[idx].bin_or_default(key);
+            if bin.is_empty() {
+                *bin = vals;
+            } else {
+                bin.extend(vals);
+            }
+        }
+        self
+    }
+
+    /// The width and height of the world if it wraps around.
+    pub fn wrap(&self) -> Option<[f32; 2]> {
+        self.wrap.map(|w| w.size)
+    }
+
+    /// Tells `query_one_ring` that nothing is stored outside of `[0, w) x [0, h)`, so that
+    /// neighbors entirely outside of it are not looked up, or removes the bounds for `None`.
+    /// Items can still be added outside of the bounds, but may be missed by those queries.
+    /// This has no effect on a world which wraps.
+    pub fn with_world_bounds(mut self, size: Option<[f32; 2]>) -> Self {
+        self.world_bounds = size;
+        self
+    }
+
+    /// The width and height of the world set by `with_world_bounds`.
+    pub fn world_bounds(&self) -> Option<[f32; 2]> {
+        self.world_bounds
+    }
+
+    /// Moves all items into a spatial hash with fewer buckets. Useful when the load factor is
+    /// low, since iteration walks every bucket even if most are empty.
+    pub fn shrink_buckets<const M: usize>(self) -> SpatialHash<T, M, S, B> {
+        assert!(M <= N, "shrink_buckets cannot grow from {N} to {M} buckets");
+        self.resize_buckets()
+    }
+
+    /// Adds an item to this spatial hash. Returns the item set that it was added to.
+    /// This can be used to sort the items for later querying.
+    /// Mainly exists so you can have a z buffer in it.
+    pub fn add(&mut self, x: f32, y: f32, t: T) -> &mut [T] {
+        self.add_indexed(x, y, t).2
+    }
+
+    /// Same as `add`, but also returns the bucket index and key of the bin, as reported by
+    /// `idx`, `bins` and `iter`.
+    pub fn add_indexed(&mut self, x: f32, y: f32, t: T) -> (usize, [i32; 2], &mut [T]) {
+        let (idx, key) = self.idx(x, y);
+        let v = self.data[idx].bin_or_default(key);
+        v.push(t);
+        (idx, key, v)
+    }
+
+    /// Same as `add`, but if the bin containing (x,y) has to be created, it is allocated with
+    /// room for `cap` items. Existing bins are left as they are.
+    pub fn add_with_capacity_hint(&mut self, x: f32, y: f32, t: T, cap: usize) -> &mut [T] {
+        let (idx, key) = self.idx(x, y);
+        let v = self.data[idx].bin_or_insert_with(key, || Vec::with_capacity(cap));
+        v.push(t);
+        v
+    }
+
+    /// Adds many items at once. Items are split by bucket and sorted by key first, so each bin
+    /// is looked up only once and every map is filled in key order, which avoids scattered map
+    /// inserts when the input is in no particular order. Items in the same bin keep the order
+    /// they were given in, as with repeated `add`s.
+    pub fn add_batch(&mut self, points: impl IntoIterator<Item = (f32, f32, T)>) {
+        let mut buckets = [(); N].map(|_| vec![]);
+        for (x, y, t) in points {
+            let (idx, key) = self.idx(x, y);
+            buckets[idx].push((key, t));
+        }
+        for (idx, mut items) in buckets.into_iter().enumerate() {
+            items.sort_by_key(|&(key, _)| key);
+            let mut items = items.into_iter().peekable();
+            while let Some((key, t)) = items.next() {
+                let bin = self.data[idx].bin_or_default(key);
+                bin.push(t);
+                bin.extend(std::iter::from_fn(|| {
+                    items.next_if(|&(k, _)| k == key).map(|(_, t)| t)
+                }));
+            }
+        }
+    }
+
+    /// Removes the first item in the bin containing (x,y) which satisfies `pred`, removing the
+    /// bin as well if it is left empty.
+    pub fn remove(&mut self, x: f32, y: f32, pred: impl FnMut(&T) -> bool) -> Option<T> {
+        self.remove_reporting(x, y, pred).0
+    }
+
+    /// Same as `remove`, but also returns the number of items left in the bin.
+    pub fn remove_reporting(
+        &mut self,
+        x: f32,
+        y: f32,
+        pred: impl FnMut(&T) -> bool,
+    ) -> (Option<T>, usize) {
+        let (idx, key) = self.idx(x, y);
+        let Some(bin) = self.data[idx].get_bin_mut(&key) else {
+            return (None, 0);
+        };
+        let removed = bin.iter().position(pred).map(|i| bin.remove(i));
+        let left = bin.len();
+        if left == 0 {
+            self.data[idx].remove_bin(&key);
+        }
+        (removed, left)
+    }
+
+    /// Removes every item equal to `item` from the bin containing (x,y), returning how many
+    /// were removed. The bin is removed if it becomes empty.
+    pub fn remove_item(&mut self, x: f32, y: f32, item: &T) -> usize
+    where
+        T: PartialEq,
+    {
+        self.remove_equal(self.key(x, y), item)
+    }
+
+    /// Same as `remove_item`, but also removes equal items from each neighboring cell, which
+    /// retracts every copy inserted by `add_one_ring` at (x,y).
+    pub fn remove_item_one_ring(&mut self, x: f32, y: f32, item: &T) -> usize
+    where
+        T: PartialEq,
+    {
+        let center = self.key(x, y);
+        self.neighbor_keys_of(center)
+            .chain(iter::once(center))
+            .map(|key| self.remove_equal(key, item))
+            .sum()
+    }
+
+    /// Removes the item at `pos` in the bin with the given bucket index and key, as returned
+    /// by `idx`, by swapping the last item of the bin into its place. This is O(1), but moves
+    /// that last item to `pos`. The bin is removed if it becomes empty. Returns `None` if
+    /// there is no such bin or `pos` is past its end.
+    pub fn remove_at(&mut self, idx: usize, key: [i32; 2], pos: usize) -> Option<T> {
+        let bins = self.data.get_mut(idx)?;
+        let bin = bins.get_bin_mut(&key)?;
+        if pos >= bin.len() {
+            return None;
+        }
+        let removed = bin.swap_remove(pos);
+        if bin.is_empty() {
+            bins.remove_bin(&key);
+        }
+        Some(removed)
+    }
+
+    fn remove_equal(&mut self, key: [i32; 2], item: &T) -> usize
+    where
+        T: PartialEq,
+    {
+        let idx = self.key_idx(key);
+        let Some(bin) = self.data[idx].get_bin_mut(&key) else {
+            return 0;
+        };
+        let before = bin.len();
+        bin.retain(|t| t != item);
+        let removed = before - bin.len();
+        if bin.is_empty() {
+            self.data[idx].remove_bin(&key);
+        }
+        removed
+    }
+
+    /// Removes every item in the bin containing (x,y), along with the bin itself.
+    pub fn clear_bin(&mut self, x: f32, y: f32) {
+        let (idx, key) = self.idx(x, y);
+        self.data[idx].remove_bin(&key);
+    }
+
+    /// Removes every bin whose cell overlaps the rectangle from `min` to `max`, including any
+    /// items in those bins which are outside of it. Hexes and triangles are counted as
+    /// overlapping if their bounding box does.
+    pub fn clear_region(&mut self, min: [f32; 2], max: [f32; 2]) {
+        // only hexes and triangles just outside of the rectangle are included by `rect_keys`.
+        let exact = matches!(
+            self.kind,
+            CoordinateKind::Cube { .. } | CoordinateKind::Rect { .. }
+        );
+        for key in self.rect_keys(min, max) {
+            let (lo, hi) = self.kind.bounds(key);
+            if !exact && (0..2).any(|i| hi[i] <= min[i] || max[i] < lo[i]) {
+                continue;
+            }
+            let idx = self.key_idx(key);
+            self.data[idx].remove_bin(&key);
+        }
+    }
+
+    /// The bin containing (x,y), inserting an empty one if there is none.
+    pub fn entry(&mut self, x: f32, y: f32) -> &mut Vec<T> {
+        let (idx, key) = self.idx(x, y);
+        self.data[idx].bin_or_default(key)
+    }
+
+    /// Same as `add`, but also reports what was in the cell before insertion.
+    pub fn add_reporting(&mut self, x: f32, y: f32, t: T) -> AddResult<'_, T> {
+        let (idx, key) = self.idx(x, y);
+        let v = self.data[idx].bin_or_default(key);
+        let prior_count = v.len();
+        v.push(t);
+        AddResult {
+            slice: v,
+            was_new_cell: prior_count == 0,
+            prior_count,
+        }
+    }
+
+    /// Same as `add`, but accepts any 2D point type, such as `[f32; 2]`, `(f32, f32)` or
+    /// `mint::Point2<f32>` and `glam::Vec2`.
+    #[inline]
+    pub fn add_vec(&mut self, p: impl Into<[f32; 2]>, t: T) -> &mut [T] {
+        let [x, y] = p.into();
+        self.add(x, y, t)
+    }
+
+    /// Returns if two coordinates fall into the same bin for this spatial hash
+    pub fn same_bin(&self, x: f32, y: f32, a: f32, b: f32) -> bool {
+        self.idx(x, y).1 == self.idx(a, b).1
+    }
+    /// Adds a copy of the item to the cell containing (x,y) and each of its neighbors, calling
+    /// `cb` on each bin after insertion. The item is cloned for all but the last bin.
+    pub fn add_one_ring(&mut self, x: f32, y: f32, t: T, cb: impl Fn(&mut [T]))
+    where
+        T: Clone,
+    {
+        let center = self.key(x, y);
+        let mut keys = self
+            .neighbor_keys_of(center)
+            .chain(iter::once(center))
+            .peekable();
+        let mut t = Some(t);
+        while let Some(key) = keys.next() {
+            let item = if keys.peek().is_some() {
+                t.clone()
+            } else {
+                t.take()
+            };
+            let idx = self.key_idx(key);
+            let v = self.data[idx].bin_or_default(key);
+            v.push(item.unwrap());
+            cb(v)
+        }
+    }
+    /// Adds an item to this spatial hash, keeping at most one item in its bin. If the bin is
+    /// not empty, the new item is combined with each existing item in order, as
+    /// `resolve(resolve(t, first), second)` and so on, and only the result is kept.
+    pub fn add_with_conflict_resolution(
+        &mut self,
+        x: f32,
+        y: f32,
+        t: T,
+        mut resolve: impl FnMut(T, T) -> T,
+    ) {
+        let (idx, key) = self.idx(x, y);
+        let v = self.data[idx].bin_or_default(key);
+        let new = v.drain(..).fold(t, &mut resolve);
+        v.push(new);
+    }
+
+    /// Same as `add_with_conflict_resolution`, but only if the bin holds at most one item.
+    /// Otherwise the bin is left as it is, and the item is given back.
+    pub fn try_add_with_conflict_resolution(
+        &mut self,
+        x: f32,
+        y: f32,
+        t: T,
+        resolve: impl FnOnce(T, T) -> T,
+    ) -> Result<(), T> {
+        let (idx, key) = self.idx(x, y);
+        let v = self.data[idx].bin_or_default(key);
+        match v.len() {
+            0 => v.push(t),
+            1 => {
+                let new = resolve(t, v.pop().unwrap());
+                v.push(new);
+            }
+            _ => return Err(t),
+        }
+        Ok(())
+    }
+
+    /// adds a line to the spatial hash using the bresenham algorithm.
+    pub fn add_line_bresenham(&mut self, l_start: [f32; 2], l_end: [f32; 2], t: T)
+    where
+        T: Copy,
+    {
+        let (_, l_start) = self.idx(l_start[0], l_start[1]);
+        let (_, l_end) = self.idx(l_end[0], l_end[1]);
+        for [x, y] in lines::bresenham(l_start, l_end) {
+            let idx = self.coord_idx(Euclidean { x, y });
+            self.data[idx].bin_or_default([x, y]).push(t);
+        }
+    }
+
+    /// Adds a copy of the item to each cell covered by the antialiased line from `start` to
+    /// `end`. Only supported for cubes and rectangles, and returns `None` without adding
+    /// anything for other kinds, like `segment_cells` and `query_line_aa`.
+    pub fn add_line_wu(&mut self, start: [f32; 2], end: [f32; 2], t: T) -> Option<()>
+    where
+        T: Clone,
+    {
+        self.add_line_wu_with(start, end, |_, bin| bin.push(t.clone()))
+    }
+
+    /// Calls `f` with the bin of each cell covered by the antialiased line from `start` to
+    /// `end`, and the fraction of that cell covered by the line. Bins are created if needed,
+    /// and each cell is visited once. Only supported for cubes and rectangles, and returns
+    /// `None` without calling `f` for other kinds.
+    pub fn add_line_wu_with(
+        &mut self,
+        start: [f32; 2],
+        end: [f32; 2],
+        mut f: impl FnMut(f32, &mut Vec<T>),
+    ) -> Option<()> {
+        let to_cells = self.wu_cells()?;
+        // a line longer than a wrapping world covers some cells more than once.
+        let mut covered = BTreeMap::new();
+        let cells = self.wrap_cells();
+        for (key, cov) in lines::wu_coverage(to_cells(start), to_cells(end)) {
+            if cov > 0. {
+                let c = covered.entry(wrapped(key, cells)).or_insert(cov);
+                *c = c.max(cov);
+            }
+        }
+        for (key, cov) in covered {
+            let idx = self.key_idx(key);
+            f(cov, self.data[idx].bin_or_default(key));
+        }
+        Some(())
+    }
+
+    /// Maps points into the space of `lines::wu_coverage`, where rectangular cells are unit
+    /// squares centered on integer coordinates.
+    fn wu_cells(&self) -> Option<impl Fn([f32; 2]) -> [f32; 2]> {
+        let (size, o) = self.kind.axis_aligned()?;
+        Some(move |p: [f32; 2]| [0, 1].map(|i| (p[i] - o[i]) / size[i] - 0.5))
+    }
+
+    /// The bins of each cell the segment from `start` to `end` passes through, in order from
+    /// `start`. Each cell is yielded once.
+    pub fn query_segment(&self, start: [f32; 2], end: [f32; 2]) -> impl Iterator<Item = &[T]> + '_ {
+        let root3: f32 = (3.0f32).sqrt();
+        // every cell edge lies on a line where one of these crosses an integer.
+        let normals = match self.kind {
+            CoordinateKind::Cube { side_len, .. } => {
+                vec![[1. / side_len, 0.], [0., 1. / side_len]]
+            }
+            CoordinateKind::Rect { w, h, .. } => vec![[1. / w, 0.], [0., 1. / h]],
+            CoordinateKind::Hex {
+                circumradius,
+                orientation,
+            } => {
+                let apothem = circumradius * root3 / 2.;
+                [[1., 0.], [0.5, root3 / 2.], [-0.5, root3 / 2.]]
+                    .into_iter()
+                    .map(|n| orientation.flip(n).map(|c| c / apothem))
+                    .collect()
+            }
+            // the barycentric values which are rounded by `TriCoord::new`.
+            CoordinateKind::Tri { side_len } => vec![
+                [1. / side_len, -root3 / 3. / side_len],
+                [0., root3 * 2. / 3. / side_len],
+                [-1. / side_len, -root3 / 3. / side_len],
+            ],
+        };
+        let lerp = |t: f32| [0, 1].map(|i| start[i] + (end[i] - start[i]) * t);
+        // crossings are the same fraction along the segment wherever the grid is placed.
+        let o = self.kind.grid_origin();
+        let shift = |p: [f32; 2]| [p[0] - o[0], p[1] - o[1]];
+        let mut keys = lines::crossings(shift(start), shift(end), &normals)
+            .windows(2)
+            .map(|w| {
+                let [x, y] = lerp((w[0] + w[1]) / 2.);
+                self.key(x, y)
+            })
+            .collect::<Vec<_>>();
+        // cells are convex, so a cell is never entered twice.
+        keys.dedup();
+        keys.into_iter()
+            .filter_map(|key| self.bin(key).map(Vec::as_slice))
+    }
+
+    /// Each cell crossed by the segment from `start` to `end`, along with the points where the
+    /// segment enters and exits that cell. Only supported for cubes and rectangles, and `None`
+    /// for other kinds.
+    pub fn segment_cells(
+        &self,
+        start: [f32; 2],
+        end: [f32; 2],
+    ) -> Option<impl Iterator<Item = ([i32; 2], [f32; 2], [f32; 2])>> {
+        let ([w, h], [ox, oy]) = self.kind.axis_aligned()?;
+        // stretch y so that rectangles become squares of side w.
+        let s = w / h;
+        let to_grid = move |[x, y]: [f32; 2]| [x - ox, (y - oy) * s];
+        let from_grid = move |[x, y]: [f32; 2]| [x + ox, y / s + oy];
+        let cells = self.wrap_cells();
+        Some(
+            lines::dda(to_grid(start), to_grid(end), w)
+                .map(move |(cell, a, b)| (wrapped(cell, cells), from_grid(a), from_grid(b))),
+        )
+    }
+
+    /// Each occupied cell touched by the antialiased line from `start` to `end`, along with the
+    /// fraction of the cell covered by the line. Only supported for cubes and rectangles, and
+    /// `None` for other kinds.
+    pub fn query_line_aa(
+        &self,
+        start: [f32; 2],
+        end: [f32; 2],
+    ) -> Option<impl Iterator<Item = (f32, &[T])> + '_> {
+        let to_cells = self.wu_cells()?;
+        let cells = self.wrap_cells();
+        Some(
+            lines::wu_coverage(to_cells(start), to_cells(end)).filter_map(move |(key, cov)| {
+                Some((cov, self.bin(wrapped(key, cells))?.as_slice()))
+            }),
+        )
+    }
+
+    pub fn query(&self, x: f32, y: f32) -> &[T] {
+        let (idx, key) = self.idx(x, y);
+        self.data[idx]
+            .get_bin(&key)
+            .map(Vec::as_slice)
+            .unwrap_or(&[])
+    }
+
+    /// Same as `query`, but also returns the key of the cell containing (x,y), so it doesn't
+    /// need to be found again with `idx`.
+    pub fn query_keyed(&self, x: f32, y: f32) -> ([i32; 2], &[T]) {
+        let (idx, key) = self.idx(x, y);
+        let vals = self.data[idx].get_bin(&key).map(Vec::as_slice);
+        (key, vals.unwrap_or(&[]))
+    }
+
+    /// Same as `query_keyed`, but keeps returning the cell `prev_key` while (x,y) is within
+    /// `margin` of it, so that a point jittering around the boundary between two cells does
+    /// not flip between them. Pass the key returned by the previous call as `prev_key`.
+    /// The margin may be slightly larger near the corners of hexagons and triangles.
+    pub fn query_stable(
+        &self,
+        x: f32,
+        y: f32,
+        prev_key: [i32; 2],
+        margin: f32,
+    ) -> ([i32; 2], &[T]) {
+        let key = self.key(x, y);
+        let key = if key != prev_key
+            && self
+                .clipped_neighbor_keys(x, y, margin)
+                .any(|k| k == prev_key)
+        {
+            prev_key
+        } else {
+            key
+        };
+        (key, self.bin(key).map_or(&[], Vec::as_slice))
+    }
+
+    /// The bin containing each point, in the same order as `points`.
+    pub fn query_many<'a>(&'a self, points: &'a [[f32; 2]]) -> impl Iterator<Item = &'a [T]> + 'a {
+        points.iter().map(|&[x, y]| self.query(x, y))
+    }
+
+    /// Items in the bin containing (x,y) along with their index in that bin.
+    /// Indices are invalidated by any change to the bin.
+    pub fn query_indexed(&self, x: f32, y: f32) -> impl Iterator<Item = (usize, &T)> {
+        self.query(x, y).iter().enumerate()
+    }
+
+    /// Mutable access to the bin containing (x,y), empty if there is no such bin.
+    pub fn query_mut(&mut self, x: f32, y: f32) -> &mut [T] {
+        let (idx, key) = self.idx(x, y);
+        self.data[idx]
+            .get_bin_mut(&key)
+            .map(Vec::as_mut_slice)
+            .unwrap_or(&mut [])
+    }
+
+    /// Returns true if any item in the bin containing (x,y) satisfies `pred`.
+    pub fn contains(&self, x: f32, y: f32, pred: impl FnMut(&T) -> bool) -> bool {
+        self.query(x, y).iter().any(pred)
+    }
+
+    /// Same as `query`, but accepts any 2D point type, such as `[f32; 2]`, `(f32, f32)` or
+    /// `mint::Point2<f32>` and `glam::Vec2`.
+    #[inline]
+    pub fn query_vec(&self, p: impl Into<[f32; 2]>) -> &[T] {
+        let [x, y] = p.into();
+        self.query(x, y)
+    }
+
+    /// Query items in a close proximity to a given (x,y) coordinate. Every item within
+    /// `ring_clearance(1)` of (x,y) is in one of the yielded bins; that is the side length for
+    /// cubes, the shorter side for rectangles, the triangle height for triangles, and the
+    /// circumradius for hexes. Neighbors which have no point that close are skipped.
+    /// The bin containing (x,y) is always yielded first, followed by its neighbors in the
+    /// order of `RegularCoord::one_ring`. Cells with no bin are skipped.
+    pub fn query_one_ring(&self, x: f32, y: f32) -> impl Iterator<Item = &[T]> + '_ {
+        self.query_one_ring_keys(x, y)
+            .filter_map(|key| self.bin(key).map(Vec::as_slice))
+    }
+
+    /// Keys of the cells looked up by `query_one_ring`, whether or not they have a bin: the
+    /// cell containing (x,y) followed by its neighbors which are not clipped. If world bounds
+    /// are set with `with_world_bounds`, neighbors entirely outside of them are also skipped.
+    pub fn query_one_ring_keys(&self, x: f32, y: f32) -> impl Iterator<Item = [i32; 2]> + '_ {
+        let neighbors = self.clipped_neighbor_keys(x, y, self.kind.ring_clearance(1));
+        // a wrapping world has no cells outside of it.
+        let world = self.world_bounds.filter(|_| self.wrap.is_none());
+        let in_world = move |key: &[i32; 2]| {
+            world.is_none_or(|[w, h]| {
+                let (lo, hi) = self.kind.bounds(*key);
+                lo[0] < w && lo[1] < h && hi[0] > 0. && hi[1] > 0.
+            })
+        };
+        iter::once(self.key(x, y)).chain(neighbors.filter(in_world))
+    }
+
+    /// Keys of the neighbors of the cell containing (x,y) which may have a point within
+    /// `radius` of it, as decided by `one_ring_clipped` for each kind of cell.
+    fn clipped_neighbor_keys(&self, x: f32, y: f32, radius: f32) -> impl Iterator<Item = [i32; 2]> {
+        match self.kind {
+            CoordinateKind::Cube { side_len: w, .. } | CoordinateKind::Rect { w, .. } => {
+                let h = match self.kind {
+                    CoordinateKind::Rect { h, .. } => h,
+                    _ => w,
+                };
+                // clip before wrapping, so that neighbors are on the same side as (x,y).
+                let [u, v] = self.kind.key(x, y);
+                let ax = Euclidean { x: u, y: v };
+                let [ox, oy] = self.kind.grid_origin();
+                let cells = self.wrap_cells();
+                Tri::A(
+                    ax.one_ring_clipped_rect(x - ox, y - oy, w, h, radius)
+                        .map(move |c| wrapped([c.x, c.y], cells)),
+                )
+            }
+            CoordinateKind::Tri { side_len } => {
+                let ax = TriCoord::from_euclidean(x, y, side_len);
+                Tri::B(
+                    ax.one_ring_clipped(x, y, side_len, radius)
+                        .map(|c| c.canon2d()),
+                )
+            }
+            CoordinateKind::Hex {
+                circumradius,
+                orientation,
+            } => {
+                // clip in the frame where hexagons are pointy top.
+                let [x, y] = orientation.flip([x, y]);
+                let ax = HexAxial::from_euclidean(x, y, circumradius);
+                Tri::C(
+                    ax.one_ring_clipped(x, y, circumradius, radius)
+                        .map(move |c| {
+                            let c = orientation.flip_hex(c);
+                            [c.q, c.r]
+                        }),
+                )
+            }
+        }
+    }
+
+    /// Every item in the bins yielded by `query_one_ring`, starting with the center bin.
+    pub fn query_one_ring_items(&self, x: f32, y: f32) -> impl Iterator<Item = &T> + '_ {
+        self.query_one_ring(x, y).flatten()
+    }
+
+    /// The items of `query_one_ring_items` collected into a `Vec`, which is easier to store
+    /// than the iterator.
+    pub fn query_with_neighbors_collected(&self, x: f32, y: f32) -> Vec<&T> {
+        self.query_one_ring_items(x, y).collect()
+    }
+
+    /// Every unordered pair of distinct items which share a bin, for use as a broadphase. Each
+    /// pair is yielded once. For items with a size up to that of a cell, use
+    /// `potential_pairs_one_ring` so that pairs across neighboring cells are included.
+    pub fn potential_pairs(&self) -> impl Iterator<Item = (&T, &T)> + '_ {
+        self.bins().flat_map(|(_, _, vals)| pairs_within(vals))
+    }
+
+    /// Same as `potential_pairs`, but also includes each pair of items in neighboring cells.
+    pub fn potential_pairs_one_ring(&self) -> impl Iterator<Item = (&T, &T)> + '_ {
+        // each pair of neighboring cells is visited once, from the one with the lower key.
+        let across = self.bins().flat_map(move |(_, key, vals)| {
+            self.neighbor_keys_of(key)
+                .filter(move |&n| n > key)
+                .filter_map(|n| self.bin(n))
+                .flat_map(move |other| {
+                    vals.iter()
+                        .flat_map(move |a| other.iter().map(move |b| (a, b)))
+                })
+        });
+        self.potential_pairs().chain(across)
+    }
+
+    /// Query all bins whose cells overlap the rectangle from `min` to `max`. For hexes and
+    /// triangles this may also yield some bins just outside of the rectangle, but never misses
+    /// one inside of it.
+    pub fn query_rect(&self, min: [f32; 2], max: [f32; 2]) -> impl Iterator<Item = &[T]> + '_ {
+        self.rect_keys(min, max)
+            .into_iter()
+            .filter_map(|key| self.bin(key).map(Vec::as_slice))
+    }
+
+    /// The keys of the cubes or rectangles overlapping the rectangle from `min` to `max`, each
+    /// once, along with how many there are.
+    fn axis_keys(&self, min: [f32; 2], max: [f32; 2]) -> (u64, impl Iterator<Item = [i32; 2]>) {
+        let [lx, ly] = self.kind.key(min[0], min[1]);
+        let [hx, hy] = self.kind.key(max[0], max[1]);
+        let cells = self.wrap_cells();
+        // a rectangle at least as wide as a wrapping world covers every column once.
+        let span = move |lo: i32, hi: i32, i: usize| match cells {
+            Some(n) if hi as i64 - lo as i64 >= n[i] as i64 => 0..=n[i] - 1,
+            _ => lo..=hi,
+        };
+        let count = [span(lx, hx, 0), span(ly, hy, 1)]
+            .map(|s| span_len(*s.start(), *s.end()))
+            .into_iter()
+            .fold(1, u64::saturating_mul);
+        let keys =
+            span(lx, hx, 0).flat_map(move |x| span(ly, hy, 1).map(move |y| wrapped([x, y], cells)));
+        (count, keys)
+    }
+
+    /// The keys of every cell which may contain a point within `rad` of (x,y), each once, along
+    /// with how many there are. These come from bounds on the keys of each kind of cell rather
+    /// than from expanding rings, so nothing is allocated.
+    fn radius_keys(&self, x: f32, y: f32, rad: f32) -> (u64, impl Iterator<Item = [i32; 2]>) {
+        match self.kind {
+            CoordinateKind::Cube { .. } | CoordinateKind::Rect { .. } => {
+                let (count, keys) = self.axis_keys([x - rad, y - rad], [x + rad, y + rad]);
+                (count, Tri::A(keys))
+            }
+            CoordinateKind::Hex {
+                circumradius,
+                orientation,
+            } => {
+                let [x, y] = orientation.flip([x, y]);
+                let (count, hexes) = HexAxial::near(x, y, rad, circumradius);
+                let keys = hexes.map(move |h| {
+                    let h = orientation.flip_hex(h);
+                    [h.q, h.r]
+                });
+                (count, Tri::B(keys))
+            }
+            CoordinateKind::Tri { side_len } => {
+                let ([lx, lt], [hx, ht]) = TriCoord::canon2d_bounds(x, y, rad, side_len);
+                let count = span_len(lx, hx).saturating_mul(span_len(lt, ht));
+                let keys = (lt..=ht).flat_map(move |t| (lx..=hx).map(move |x| [x, t]));
+                (count, Tri::C(keys))
+            }
+        }
+    }
+
+    /// The keys of the cells searched by `query_rect`.
+    fn rect_keys(&self, min: [f32; 2], max: [f32; 2]) -> Vec<[i32; 2]> {
+        match self.kind {
+            CoordinateKind::Cube { .. } | CoordinateKind::Rect { .. } => {
+                self.axis_keys(min, max).1.collect()
+            }
+            // Sample the rectangle densely enough that every point in it is closer to a sample
+            // than `ring_clearance(1)`, so its cell neighbors the cell of that sample.
+            CoordinateKind::Hex { .. } | CoordinateKind::Tri { .. } => {
+                let step = self.kind.ring_clearance(1);
+                let samples = |lo: f32, hi: f32| {
+                    let n = ((hi - lo) / step).ceil().max(0.) as usize;
+                    (0..=n).map(move |i| (lo + i as f32 * step).min(hi))
+                };
+                let mut keys = std::collections::BTreeSet::new();
+                for x in samples(min[0], max[0]) {
+                    for y in samples(min[1], max[1]) {
+                        let key = self.key(x, y);
+                        keys.insert(key);
+                        keys.extend(self.neighbor_keys_of(key));
+                    }
+                }
+                keys.into_iter().collect()
+            }
+        }
+    }
+
+    /// Query only the neighbors of the cell containing (x,y), skipping the cell itself.
+    /// Useful when the querying item is itself stored in the center cell.
+    pub fn query_ring_only(&self, x: f32, y: f32) -> impl Iterator<Item = &[T]> + '_ {
+        self.neighbor_keys(x, y)
+            .filter_map(|key| self.bin(key).map(Vec::as_slice))
+    }
+
+    /// Query all bins within `n` rings of the cell containing (x,y), nearest rings first.
+    /// `n == 0` is only the cell itself, and `n == 1` is the same set as `query_one_ring`
+    /// without clipping any neighbors.
+    pub fn query_n_ring(&self, x: f32, y: f32, n: u32) -> impl Iterator<Item = &[T]> + '_ {
+        self.rings(x, y)
+            .take(n as usize + 1)
+            .flatten()
+            .filter_map(|key| self.bin(key).map(Vec::as_slice))
+    }
+
+    /// Expands outwards from the cell containing (x,y), yielding the keys of each ring of
+    /// cells in turn, starting with just the cell itself.
+    fn rings(&self, x: f32, y: f32) -> impl Iterator<Item = Vec<[i32; 2]>> + '_ {
+        let start = self.key(x, y);
+        let mut visited = std::collections::HashSet::from([start]);
+        let mut frontier = vec![start];
+        iter::from_fn(move || {
+            let next = frontier
+                .iter()
+                .flat_map(|&key| self.neighbor_keys_of(key))
+                .filter(|&n| visited.insert(n))
+                .collect();
+            Some(std::mem::replace(&mut frontier, next))
+        })
+    }
+
+    /// The area covered by each cell, such as for turning the number of items in a bin into
+    /// a density.
+    pub fn cell_area(&self) -> f32 {
+        match self.kind {
+            CoordinateKind::Cube { side_len, .. } => Euclidean::<i32>::cell_area(side_len),
+            CoordinateKind::Rect { w, h, .. } => w * h,
+            CoordinateKind::Hex { circumradius, .. } => HexAxial::<i32>::cell_area(circumradius),
+            CoordinateKind::Tri { side_len } => TriCoord::<i32>::cell_area(side_len),
+        }
+    }
+
+    /// The min and max corners of the axis aligned bounding box of the cell containing (x,y).
+    /// For cubes and rectangles this is the cell itself.
+    pub fn cell_bounds(&self, x: f32, y: f32) -> ([f32; 2], [f32; 2]) {
+        self.kind.bounds(self.key(x, y))
+    }
+
+    /// The center of the cell containing (x,y), whether or not it is occupied.
+    pub fn nearest_centroid(&self, x: f32, y: f32) -> [f32; 2] {
+        self.kind.centroid(self.key(x, y))
+    }
+
+    /// The center of the occupied cell whose center is nearest to (x,y), or `None` if the
+    /// hash is empty. Unlike `nearest_centroid`, this skips over empty cells.
+    pub fn nearest_occupied_centroid(&self, x: f32, y: f32) -> Option<[f32; 2]> {
+        self.nearest_nonempty(x, y).map(|(c, _)| c)
+    }
+
+    /// The center and items of the occupied cell whose center is nearest to (x,y), expanding
+    /// ring by ring from the cell containing (x,y). Returns `None` if the hash is empty. If
+    /// the world wraps, distances are measured the shorter way around it.
+    pub fn nearest_nonempty(&self, x: f32, y: f32) -> Option<([f32; 2], &[T])> {
+        if self.is_empty() {
+            return None;
+        }
+        let dist = |c: [f32; 2]| self.wrapped_dist([x, y], c);
+        let mut best: Option<(f32, [f32; 2], &[T])> = None;
+        for (rings, ring) in self.rings(x, y).enumerate() {
+            for key in ring {
+                match self.bin(key) {
+                    Some(v) if !v.is_empty() => {
+                        let c = self.kind.centroid(key);
+                        let d = dist(c);
+                        if best.is_none_or(|(bd, _, _)| d < bd) {
+                            best = Some((d, c, v));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            // centroids lie within their cell, so farther rings can't have a closer one.
+            match best {
+                Some((d, c, v)) if d <= self.kind.ring_clearance(rings as u32) => {
+                    return Some((c, v))
+                }
+                _ => {}
+            }
+        }
+        unreachable!()
+    }
+
+    /// Returns the `k` items nearest to (x,y), sorted by `dist`, which should return the
+    /// distance from an item to (x,y).
+    /// Cells are expanded ring by ring until `k` candidates are found and no unvisited cell
+    /// could contain anything closer than the `k`th candidate.
+    /// Returns fewer than `k` items if the hash has fewer than `k` items.
+    pub fn k_nearest(&self, x: f32, y: f32, k: usize, dist: impl Fn(&T) -> f32) -> Vec<&T> {
+        let total = self.len();
+        if k == 0 || total == 0 {
+            return vec![];
+        }
+        let mut candidates = vec![];
+        for (rings, ring) in self.rings(x, y).enumerate() {
+            for key in ring {
+                let items = self.bin(key).map(Vec::as_slice).unwrap_or(&[]);
+                candidates.extend(items.iter().map(|t| (dist(t), t)));
+            }
+            if candidates.len() == total {
+                break;
+            }
+            if candidates.len() >= k {
+                candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+                if candidates[k - 1].0 <= self.kind.ring_clearance(rings as u32) {
+                    break;
+                }
+            }
+        }
+        candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+        candidates.truncate(k);
+        candidates.into_iter().map(|(_, t)| t).collect()
+    }
+
+    /// Calls `f` once with the bin of each occupied cell which may contain a point within
+    /// `rad` of (x,y). Every item within `rad` of (x,y) is in one of those bins, but they may
+    /// contain farther ones too. If there are more such cells than bins, such as for an
+    /// infinite `rad`, every bin is visited instead. Nothing is allocated, and `f` is not
+    /// called for a negative or `NaN` radius.
+    pub fn for_each_cell_in_radius<'a>(
+        &'a self,
+        x: f32,
+        y: f32,
+        rad: f32,
+        mut f: impl FnMut(&'a [T]),
+    ) {
+        if rad.is_nan() || rad < 0. {
+            return;
+        }
+        let (cells, keys) = self.radius_keys(x, y, rad);
+        let bins = self.data.iter().map(B::num_bins).sum::<usize>();
+        let mut visit = |vals: &'a Vec<T>| {
+            if !vals.is_empty() {
+                f(vals);
+            }
+        };
+        if cells > bins as u64 {
+            self.data
+                .iter()
+                .flat_map(B::bins)
+                .for_each(|(_, vals)| visit(vals));
+        } else {
+            keys.filter_map(|key| self.bin(key)).for_each(visit);
+        }
+    }
+
+    /// Number of items within `rad` of (x,y), where `pos` returns the position of an item.
+    /// Items are counted in the bins visited by `for_each_cell_in_radius`, so nothing is
+    /// allocated.
+    pub fn count_in_radius(&self, x: f32, y: f32, rad: f32, pos: impl Fn(&T) -> [f32; 2]) -> usize {
+        let r2 = rad * rad;
+        let mut count = 0;
+        self.for_each_cell_in_radius(x, y, rad, |vals| {
+            count += vals
+                .iter()
+                .filter(|t| {
+                    let [a, b] = pos(t);
+                    (a - x) * (a - x) + (b - y) * (b - y) <= r2
+                })
+                .count();
+        });
+        count
+    }
+
+    /// Every item within `rad` of (x,y), nearest first, where `pos` returns the position of an
+    /// item. Items at the same distance are ordered by position, then by the order in which
+    /// they are stored.
+    pub fn query_radius_sorted(
+        &self,
+        x: f32,
+        y: f32,
+        rad: f32,
+        pos: impl Fn(&T) -> [f32; 2],
+    ) -> Vec<&T> {
+        let r2 = rad * rad;
+        let mut found = vec![];
+        self.for_each_cell_in_radius(x, y, rad, |vals| {
+            found.extend(vals.iter().filter_map(|t| {
+                let p @ [a, b] = pos(t);
+                let d2 = (a - x) * (a - x) + (b - y) * (b - y);
+                (d2 <= r2).then_some((d2, p, t))
+            }));
+        });
+        found.sort_by(|(da, pa, _), (db, pb, _)| {
+            da.total_cmp(db)
+                .then(pa[0].total_cmp(&pb[0]))
+                .then(pa[1].total_cmp(&pb[1]))
+        });
+        found.into_iter().map(|(_, _, t)| t).collect()
     }
     /*
     pub fn query_radius(&self, x: f32, y: f32, rad: f32) -> impl Iterator<Item = &T> + '_ {
@@ -38,8 +38,55 @@ pub fn bresenham([x0, y0]: [i32; 2], [x1, y1]: [i32; 2]) -> impl Iterator<Item =
     first.chain(rest)
 }
 
+/// Walks the square cells of size `side_len` crossed by the segment from `start` to `end`,
+/// yielding each cell along with the points where the segment enters and exits it.
+pub fn dda(
+    start: [f32; 2],
+    end: [f32; 2],
+    side_len: f32,
+) -> impl Iterator<Item = ([i32; 2], [f32; 2], [f32; 2])> {
+    let [x0, y0] = start;
+    let d = [end[0] - x0, end[1] - y0];
+    let lerp = move |t: f32| [x0 + d[0] * t, y0 + d[1] * t];
+
+    let mut cell = start.map(|v| (v / side_len).floor() as i32);
+    let step = d.map(|v| if v > 0. { 1 } else { -1 });
+    let t_delta = d.map(|v| side_len / v.abs());
+    let mut t_max = [0, 1].map(|i| {
+        if d[i] == 0. {
+            return f32::INFINITY;
+        }
+        let boundary = (cell[i] + (d[i] > 0.) as i32) as f32 * side_len;
+        (boundary - start[i]) / d[i]
+    });
+
+    let mut t = 0.;
+    std::iter::from_fn(move || {
+        if t >= 1. {
+            return None;
+        }
+        let axis = if t_max[0] < t_max[1] { 0 } else { 1 };
+        let t_next = t_max[axis].min(1.);
+        let out = (cell, lerp(t), lerp(t_next));
+        t = t_next;
+        cell[axis] += step[axis];
+        t_max[axis] += t_delta[axis];
+        Some(out)
+    })
+}
+
 // returns coordinates in whatever input coordinate system is given.
-pub fn wu([x0, y0]: [f32; 2], [x1, y1]: [f32; 2]) -> impl Iterator<Item = [i32; 2]> {
+pub fn wu(start: [f32; 2], end: [f32; 2]) -> impl Iterator<Item = [i32; 2]> {
+    wu_coverage(start, end).map(|(p, _)| p)
+}
+
+/// Wu's line algorithm, returning each pixel along with the fraction of it covered by the
+/// line. Pixels are centered on integer coordinates, and the two pixels emitted for each
+/// column have coverages which sum to 1.
+pub fn wu_coverage(
+    [x0, y0]: [f32; 2],
+    [x1, y1]: [f32; 2],
+) -> impl Iterator<Item = ([i32; 2], f32)> {
     let steep = (y1 - y0).abs() > (x1 - x0).abs();
     let (x0, y0, x1, y1) = if steep {
         (y0, x0, y1, x1)
@@ -58,40 +105,60 @@ pub fn wu([x0, y0]: [f32; 2], [x1, y1]: [f32; 2]) -> impl Iterator<Item = [i32;
     // TODO maybe use an epsilon here
     let grad = if dx.abs() < 1e-4 { 1. } else { dy / dx };
 
+    // the pair of pixels straddling y in column x.
+    let column = move |x: i32, y: f32| {
+        let iy = y.floor() as i32;
+        let cov = y - y.floor();
+        if steep {
+            [([iy, x], 1. - cov), ([iy + 1, x], cov)]
+        } else {
+            [([x, iy], 1. - cov), ([x, iy + 1], cov)]
+        }
+        .into_iter()
+    };
+
     // first endpoint
     let x_end = x0.round();
     let y_end = y0 + grad * (x_end - x0);
-    let xpxl1 = x0 as i32;
-    let ypxl1 = y_end.floor() as i32;
-    let iter = if steep {
-        [[ypxl1, xpxl1], [ypxl1 + 1, xpxl1]]
-    } else {
-        [[xpxl1, ypxl1], [xpxl1, ypxl1 + 1]]
-    }
-    .into_iter();
+    let xpxl1 = x_end as i32;
+    let iter = column(xpxl1, y_end);
 
     let inter_y = y_end + grad;
 
-    // second endpoint
+    // second endpoint, unless it is in the same column as the first.
     let x_end = x1.round();
-    let y_end = y1 + grad * (x_end * x1);
+    let y_end = y1 + grad * (x_end - x1);
     let xpxl2 = x_end as i32;
-    let ypxl2 = y_end.floor() as i32;
-    let end_iter = if steep {
-        [[ypxl2, xpxl2], [ypxl2 + 1, xpxl2]]
-    } else {
-        [[xpxl2, ypxl2], [xpxl2, ypxl2 + 1]]
-    }
-    .into_iter();
-
-    let inner = (xpxl1 + 1..xpxl2).enumerate().flat_map(move |(i, x)| {
-        let iy = (inter_y + i as f32 * grad).floor() as i32;
-        if steep {
-            [[iy, x], [iy + 1, x]]
-        } else {
-            [[x, iy], [x, iy + 1]]
-        }
+    let end_iter = (xpxl2 != xpxl1)
+        .then(|| column(xpxl2, y_end))
         .into_iter()
-    });
+        .flatten();
+
+    let inner = (xpxl1 + 1..xpxl2)
+        .enumerate()
+        .flat_map(move |(i, x)| column(x, inter_y + i as f32 * grad));
     iter.chain(end_iter).chain(inner)
 }
+
+/// The parameters in `[0, 1]` along the segment from `start` to `end` where it crosses one of
+/// the lines `n · p = k` for a normal `n` in `normals` and any integer `k`, sorted and
+/// including both ends. If every cell boundary lies on one of those lines, the segment
+/// stays in one cell between each consecutive pair.
+pub fn crossings(start: [f32; 2], end: [f32; 2], normals: &[[f32; 2]]) -> Vec<f32> {
+    let dot = |[nx, ny]: [f32; 2], [x, y]: [f32; 2]| nx * x + ny * y;
+    let mut ts = vec![0., 1.];
+    for &n in normals {
+        let (a, b) = (dot(n, start), dot(n, end));
+        if a == b {
+            continue;
+        }
+        let (lo, hi) = (a.min(b).ceil() as i32, a.max(b).floor() as i32);
+        ts.extend(
+            (lo..=hi)
+                .map(|k| (k as f32 - a) / (b - a))
+                .filter(|t| (0.0..=1.).contains(t)),
+        );
+    }
+    ts.sort_by(f32::total_cmp);
+    ts
+}
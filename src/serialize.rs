@@ -0,0 +1,84 @@
+use crate::storage::BinMap;
+use crate::{empty_buckets, CoordinateKind, Fold, SpatialHash, Wrap};
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+use std::hash::BuildHasher;
+use std::marker::PhantomData;
+
+/// Bucket indices depend on the hasher state, so only the occupied cells are stored and they
+/// are rehashed on deserialization.
+#[derive(Serialize)]
+struct SerializedRef<'a, T> {
+    kind: CoordinateKind,
+    fold: Fold,
+    wrap: Option<[f32; 2]>,
+    world_bounds: Option<[f32; 2]>,
+    bins: Vec<([i32; 2], &'a [T])>,
+}
+
+/// Fields other than the kind and bins default, so that hashes serialized before they were
+/// added can still be read.
+#[derive(Deserialize)]
+struct Serialized<T> {
+    kind: CoordinateKind,
+    #[serde(default)]
+    fold: Fold,
+    #[serde(default)]
+    wrap: Option<[f32; 2]>,
+    #[serde(default)]
+    world_bounds: Option<[f32; 2]>,
+    bins: Vec<([i32; 2], Vec<T>)>,
+}
+
+impl<T: Serialize, const N: usize, S, B: BinMap<T>> Serialize for SpatialHash<T, N, S, B> {
+    fn serialize<Ser: Serializer>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error> {
+        let bins = self
+            .data
+            .iter()
+            .flat_map(|bins| bins.bins())
+            .map(|(&key, vals)| (key, vals.as_slice()))
+            .collect();
+        SerializedRef {
+            kind: self.kind,
+            fold: self.fold,
+            wrap: self.wrap.map(|w| w.size),
+            world_bounds: self.world_bounds,
+            bins,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de, T: Deserialize<'de>, const N: usize, S: BuildHasher + Default, B: BinMap<T>>
+    Deserialize<'de> for SpatialHash<T, N, S, B>
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let Serialized {
+            kind,
+            fold,
+            wrap,
+            world_bounds,
+            bins,
+        } = Serialized::deserialize(deserializer)?;
+        let wrap = match wrap {
+            None => None,
+            Some(size) => Some(Wrap {
+                size,
+                cells: kind.wrap_cells(size).map_err(D::Error::custom)?,
+            }),
+        };
+        let mut sh = SpatialHash {
+            data: empty_buckets::<B, N>(),
+            state: S::default(),
+            kind,
+            fold,
+            wrap,
+            world_bounds,
+            _items: PhantomData,
+        };
+        for (key, vals) in bins {
+            let idx = sh.key_idx(key);
+            sh.data[idx].bin_or_default(key).extend(vals);
+        }
+        Ok(sh)
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::BuildHasher;
+use std::mem::size_of;
+
+/// A map from cell keys to the items in each cell, used for each bucket of a `SpatialHash`.
+pub trait BinMap<T>: Default {
+    /// The same kind of map holding items of another type, used by `SpatialHash::map`.
+    type Rebind<U>: BinMap<U>;
+
+    fn get_bin(&self, key: &[i32; 2]) -> Option<&Vec<T>>;
+    fn get_bin_mut(&mut self, key: &[i32; 2]) -> Option<&mut Vec<T>>;
+    /// The bin for `key`, inserting an empty one if there is none.
+    fn bin_or_default(&mut self, key: [i32; 2]) -> &mut Vec<T> {
+        self.bin_or_insert_with(key, Vec::new)
+    }
+    /// The bin for `key`, inserting the result of `f` if there is none.
+    fn bin_or_insert_with(&mut self, key: [i32; 2], f: impl FnOnce() -> Vec<T>) -> &mut Vec<T>;
+    fn insert_bin(&mut self, key: [i32; 2], vals: Vec<T>) -> Option<Vec<T>>;
+    fn remove_bin(&mut self, key: &[i32; 2]) -> Option<Vec<T>>;
+    fn retain_bins(&mut self, f: impl FnMut(&[i32; 2], &mut Vec<T>) -> bool);
+    fn clear_bins(&mut self);
+    /// Hints that about `additional` more bins will be inserted. Maps which can't allocate
+    /// ahead of time ignore it.
+    fn reserve_bins(&mut self, additional: usize) {
+        let _ = additional;
+    }
+    /// Number of bins, including empty ones.
+    fn num_bins(&self) -> usize;
+
+    fn bins<'a>(&'a self) -> impl Iterator<Item = (&'a [i32; 2], &'a Vec<T>)>
+    where
+        T: 'a;
+    fn bins_mut<'a>(&'a mut self) -> impl Iterator<Item = (&'a [i32; 2], &'a mut Vec<T>)>
+    where
+        T: 'a;
+    fn into_bins(self) -> impl Iterator<Item = ([i32; 2], Vec<T>)>;
+
+    /// Estimated bytes used by the map's nodes, not including the items in each bin.
+    fn node_bytes(&self) -> usize;
+}
+
+/// Bytes for the key and `Vec` of a single bin.
+const fn entry_bytes<T>() -> usize {
+    size_of::<[i32; 2]>() + size_of::<Vec<T>>()
+}
+
+impl<T> BinMap<T> for BTreeMap<[i32; 2], Vec<T>> {
+    type Rebind<U> = BTreeMap<[i32; 2], Vec<U>>;
+
+    #[inline]
+    fn get_bin(&self, key: &[i32; 2]) -> Option<&Vec<T>> {
+        self.get(key)
+    }
+    #[inline]
+    fn get_bin_mut(&mut self, key: &[i32; 2]) -> Option<&mut Vec<T>> {
+        self.get_mut(key)
+    }
+    #[inline]
+    fn bin_or_insert_with(&mut self, key: [i32; 2], f: impl FnOnce() -> Vec<T>) -> &mut Vec<T> {
+        self.entry(key).or_insert_with(f)
+    }
+    fn insert_bin(&mut self, key: [i32; 2], vals: Vec<T>) -> Option<Vec<T>> {
+        self.insert(key, vals)
+    }
+    fn remove_bin(&mut self, key: &[i32; 2]) -> Option<Vec<T>> {
+        self.remove(key)
+    }
+    fn retain_bins(&mut self, f: impl FnMut(&[i32; 2], &mut Vec<T>) -> bool) {
+        self.retain(f)
+    }
+    fn clear_bins(&mut self) {
+        self.clear()
+    }
+    fn num_bins(&self) -> usize {
+        self.len()
+    }
+    fn bins<'a>(&'a self) -> impl Iterator<Item = (&'a [i32; 2], &'a Vec<T>)>
+    where
+        T: 'a,
+    {
+        self.iter()
+    }
+    fn bins_mut<'a>(&'a mut self) -> impl Iterator<Item = (&'a [i32; 2], &'a mut Vec<T>)>
+    where
+        T: 'a,
+    {
+        self.iter_mut()
+    }
+    fn into_bins(self) -> impl Iterator<Item = ([i32; 2], Vec<T>)> {
+        self.into_iter()
+    }
+    fn node_bytes(&self) -> usize {
+        // each entry is amortized to about a pointer of node bookkeeping (parent pointers,
+        // lengths, and child pointers for internal nodes).
+        self.len() * (entry_bytes::<T>() + size_of::<usize>())
+    }
+}
+
+impl<T, H: BuildHasher + Default> BinMap<T> for HashMap<[i32; 2], Vec<T>, H> {
+    type Rebind<U> = HashMap<[i32; 2], Vec<U>, H>;
+
+    #[inline]
+    fn get_bin(&self, key: &[i32; 2]) -> Option<&Vec<T>> {
+        self.get(key)
+    }
+    #[inline]
+    fn get_bin_mut(&mut self, key: &[i32; 2]) -> Option<&mut Vec<T>> {
+        self.get_mut(key)
+    }
+    #[inline]
+    fn bin_or_insert_with(&mut self, key: [i32; 2], f: impl FnOnce() -> Vec<T>) -> &mut Vec<T> {
+        self.entry(key).or_insert_with(f)
+    }
+    fn insert_bin(&mut self, key: [i32; 2], vals: Vec<T>) -> Option<Vec<T>> {
+        self.insert(key, vals)
+    }
+    fn remove_bin(&mut self, key: &[i32; 2]) -> Option<Vec<T>> {
+        self.remove(key)
+    }
+    fn retain_bins(&mut self, mut f: impl FnMut(&[i32; 2], &mut Vec<T>) -> bool) {
+        self.retain(|k, v| f(k, v))
+    }
+    fn clear_bins(&mut self) {
+        self.clear()
+    }
+    fn reserve_bins(&mut self, additional: usize) {
+        self.reserve(additional)
+    }
+    fn num_bins(&self) -> usize {
+        self.len()
+    }
+    fn bins<'a>(&'a self) -> impl Iterator<Item = (&'a [i32; 2], &'a Vec<T>)>
+    where
+        T: 'a,
+    {
+        self.iter()
+    }
+    fn bins_mut<'a>(&'a mut self) -> impl Iterator<Item = (&'a [i32; 2], &'a mut Vec<T>)>
+    where
+        T: 'a,
+    {
+        self.iter_mut()
+    }
+    fn into_bins(self) -> impl Iterator<Item = ([i32; 2], Vec<T>)> {
+        self.into_iter()
+    }
+    fn node_bytes(&self) -> usize {
+        // slots are allocated for the whole capacity, with an extra control byte each.
+        self.capacity() * (entry_bytes::<T>() + 1)
+    }
+}
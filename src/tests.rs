@@ -1,13 +1,55 @@
-use crate::coordinates::TriCoord;
-use crate::{CoordinateKind, SpatialHash};
+use crate::coordinates::{euclidean_cell_center, Euclidean, HexAxial, RegularCoord, TriCoord};
+use crate::lines;
+use crate::{
+    matched_params, CellAlign, CoordinateKind, CoordinateKind3, FlatSpatialHash, Fold,
+    HexOrientation, LayeredQuery, SpatialHash, SpatialHash3, SpatialHashBuilder,
+    TrackedSpatialHash,
+};
+
+/// Small deterministic generator for test point sets.
+fn random_points(n: usize, seed: u64) -> Vec<[f32; 2]> {
+    let mut state = seed;
+    let mut next = move || {
+        state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (state >> 40) as f32 / (1u64 << 24) as f32
+    };
+    (0..n).map(|_| [next(), next()]).collect()
+}
+
+/// A cube, pointy top hex and triangle kind of the given size, for tests run on each shape.
+fn all_kinds(size: f32) -> [CoordinateKind; 3] {
+    [
+        CoordinateKind::Cube {
+            side_len: size,
+            align: CellAlign::Corner,
+        },
+        CoordinateKind::Hex {
+            circumradius: size,
+            orientation: HexOrientation::PointyTop,
+        },
+        CoordinateKind::Tri { side_len: size },
+    ]
+}
+
+fn dist([x, y]: [f32; 2], [a, b]: [f32; 2]) -> f32 {
+    ((x - a) * (x - a) + (y - b) * (y - b)).sqrt()
+}
 
 #[test]
 fn adjacent_test() {
-    let mut sh_cube = SpatialHash::new(CoordinateKind::Cube { side_len: 0.1 });
+    let mut sh_cube = SpatialHash::new(CoordinateKind::Cube {
+        side_len: 0.1,
+        align: CellAlign::Corner,
+    });
     let mut sh_tri = SpatialHash::new(CoordinateKind::Tri {
         side_len: TriCoord::height_to_side_len(0.1),
     });
-    let mut sh_hex = SpatialHash::new(CoordinateKind::Hex { circumradius: 0.1 });
+    let mut sh_hex = SpatialHash::new(CoordinateKind::Hex {
+        circumradius: 0.1,
+        orientation: HexOrientation::PointyTop,
+    });
 
     let freq = 128;
     for i in 0..freq {
@@ -20,10 +62,2971 @@ fn adjacent_test() {
         }
     }
 
-    panic!(
-        "{:?} {:?} {:?}",
+    let counts = [
         sh_cube.query(0.5, 0.5).len(),
         sh_tri.query(0.5, 0.5).len(),
         sh_hex.query(0.5, 0.5).len(),
+    ];
+    assert!(counts.iter().all(|&c| c > 0), "{counts:?}");
+}
+
+#[test]
+fn vec_input_test() {
+    let mut sh = SpatialHash::hex(0.1);
+    sh.add_vec([0.25, 0.75], 1);
+    sh.add_vec((0.25, 0.75), 2);
+    assert_eq!(sh.query_vec([0.25, 0.75]), &[1, 2]);
+    assert_eq!(sh.query_vec((0.25, 0.75)), sh.query(0.25, 0.75));
+}
+
+#[cfg(feature = "mint")]
+#[test]
+fn mint_input_test() {
+    let mut sh = SpatialHash::tri_h(0.1);
+    sh.add_vec(mint::Point2 { x: 0.3, y: 0.6 }, 1);
+    assert_eq!(sh.query_vec([0.3, 0.6]), &[1]);
+    assert_eq!(sh.query_vec(mint::Point2 { x: 0.3, y: 0.6 }), &[1]);
+}
+
+#[cfg(feature = "glam")]
+#[test]
+fn glam_input_test() {
+    let mut sh = SpatialHash::hex(0.1);
+    for (i, [x, y]) in random_points(100, 3).into_iter().enumerate() {
+        sh.add_vec(glam::Vec2::new(x, y), i);
+    }
+    for [x, y] in random_points(100, 4) {
+        assert_eq!(sh.query_vec(glam::Vec2::new(x, y)), sh.query_vec([x, y]));
+    }
+}
+
+fn sorted_wu(start: [f32; 2], end: [f32; 2]) -> Vec<[i32; 2]> {
+    let mut out = lines::wu(start, end).collect::<Vec<_>>();
+    out.sort();
+    out
+}
+
+#[test]
+fn wu_diagonal_test() {
+    let expected = vec![
+        [0, 0],
+        [0, 1],
+        [1, 1],
+        [1, 2],
+        [2, 2],
+        [2, 3],
+        [3, 3],
+        [3, 4],
+        [4, 4],
+        [4, 5],
+    ];
+    assert_eq!(sorted_wu([0., 0.], [4., 4.]), expected);
+}
+
+#[test]
+fn wu_offset_test() {
+    // Lines far from the origin used to have a wildly wrong second endpoint.
+    let expected = vec![
+        [10, 10],
+        [10, 11],
+        [11, 10],
+        [11, 11],
+        [12, 10],
+        [12, 11],
+        [13, 10],
+        [13, 11],
+        [14, 10],
+        [14, 11],
+        [15, 11],
+        [15, 12],
+    ];
+    assert_eq!(sorted_wu([10., 10.], [15., 11.]), expected);
+
+    // Steep lines are the same as shallow ones with axes swapped.
+    let swapped = sorted_wu([10., 10.], [11., 15.])
+        .into_iter()
+        .map(|[x, y]| [y, x]);
+    let mut swapped = swapped.collect::<Vec<_>>();
+    swapped.sort();
+    assert_eq!(swapped, expected);
+}
+
+#[test]
+fn wu_near_line_test() {
+    let segments = [
+        ([0.3, 0.2], [9.7, 1.1]),
+        ([-5., 3.], [7., -2.]),
+        ([20.2, 40.6], [31.9, 43.3]),
+    ];
+    for (s @ [x0, y0], e @ [x1, y1]) in segments {
+        let grad = (y1 - y0) / (x1 - x0);
+        for [x, y] in lines::wu(s, e) {
+            let line_y = y0 + grad * (x as f32 - x0);
+            assert!((y as f32 - line_y).abs() <= 1., "{s:?} {e:?}: {x} {y}");
+        }
+    }
+}
+
+#[test]
+fn memory_report_test() {
+    let mut sh = SpatialHash::cube(0.1);
+    let empty = sh.memory_report();
+    assert_eq!(empty.map_nodes, 0);
+    assert_eq!(empty.items, 0);
+
+    for i in 0..64 {
+        let i = i as f32 / 64.;
+        for j in 0..64 {
+            let j = j as f32 / 64.;
+            sh.add(i, j, [i, j]);
+        }
+    }
+    let report = sh.memory_report();
+    assert_eq!(sh.len(), 64 * 64);
+    assert!(report.items >= sh.len() * std::mem::size_of::<[f32; 2]>());
+    assert!(report.items <= 2 * sh.len() * std::mem::size_of::<[f32; 2]>());
+    assert!(report.map_nodes > 0);
+    assert_eq!(report.buckets, empty.buckets);
+}
+
+#[test]
+fn contains_test() {
+    let mut sh = SpatialHash::tri_h(0.1);
+    sh.add(0.5, 0.5, 3);
+    sh.add(0.5, 0.5, 7);
+    assert!(sh.contains(0.5, 0.5, |&v| v == 7));
+    assert!(!sh.contains(0.5, 0.5, |&v| v == 5));
+    assert!(!sh.contains(-3., 2., |_| true));
+}
+
+#[test]
+fn query_mut_test() {
+    let mut sh = SpatialHash::cube(0.1);
+    sh.add(0.25, 0.25, 1.);
+    sh.add(0.25, 0.25, 2.);
+    for v in sh.query_mut(0.25, 0.25) {
+        *v *= 10.;
+    }
+    assert_eq!(sh.query(0.25, 0.25), &[10., 20.]);
+    assert!(sh.query_mut(5., 5.).is_empty());
+}
+
+#[test]
+fn query_ring_only_test() {
+    for mut sh in [
+        SpatialHash::cube(0.1),
+        SpatialHash::tri_h(0.1),
+        SpatialHash::hex(0.1),
+    ] {
+        sh.add(0.5, 0.5, 0);
+        sh.add_one_ring(0.5, 0.5, 1, |_| {});
+        let ring = sh.query_ring_only(0.5, 0.5).flatten().collect::<Vec<_>>();
+        assert!(!ring.contains(&&0));
+        assert!(ring.contains(&&1));
+        assert!(sh.query_one_ring(0.5, 0.5).flatten().any(|&v| v == 0));
+    }
+}
+
+#[test]
+fn hex_contains_test() {
+    let r = 0.5;
+    for h in [HexAxial { q: 0, r: 0 }, HexAxial { q: 3, r: -2 }] {
+        let [cx, cy] = h.to_euclidean(r);
+        assert!(h.contains([cx, cy], r));
+        // pointy top, so the top vertex is directly above the center
+        assert!(h.contains([cx, cy + r * 0.999], r));
+        assert!(!h.contains([cx, cy + r * 1.001], r));
+        assert_eq!(HexAxial::from_euclidean(cx, cy, r), h);
+    }
+}
+
+#[test]
+fn extend_test() {
+    let points = (0..1000).map(|i| {
+        let f = i as f32;
+        ((f * 0.37).sin(), (f * 0.59).cos(), i)
+    });
+    let mut sh = SpatialHash::from_iter_with(
+        CoordinateKind::Hex {
+            circumradius: 0.1,
+            orientation: HexOrientation::PointyTop,
+        },
+        points,
+    );
+    assert_eq!(sh.len(), 1000);
+    sh.extend([(0.5, 0.5, 1000), (0.5, 0.5, 1001)]);
+    assert_eq!(sh.len(), 1002);
+    assert!(sh.contains(0.5, 0.5, |&v| v == 1001));
+}
+
+#[test]
+#[cfg(debug_assertions)]
+fn neighbor_symmetry_test() {
+    for mut sh in [
+        SpatialHash::cube(0.1),
+        SpatialHash::tri_h(0.1),
+        SpatialHash::hex(0.1),
+    ] {
+        for i in 0..32 {
+            let i = i as f32 / 32.;
+            for j in 0..32 {
+                sh.add(i, j as f32 / 32. - 0.5, ());
+            }
+        }
+        assert_eq!(sh.check_neighbor_symmetry(), Ok(()));
+    }
+
+    // A table missing one direction is caught.
+    let broken = |[x, y]: [i32; 2]| {
+        [[1, 0], [-1, 0], [0, 1]]
+            .into_iter()
+            .map(move |[dx, dy]| [x + dx, y + dy])
+    };
+    let found = crate::find_asymmetric([[0, 0]].into_iter(), broken);
+    assert_eq!(found, Some(([0, 0], [0, 1])));
+}
+
+#[test]
+fn tri_canon2d_test() {
+    for i in -20..20 {
+        for j in -20..20 {
+            let t = TriCoord::new(i as f32 * 0.13, j as f32 * 0.07, 0.1);
+            assert_eq!(TriCoord::from_canon2d(t.canon2d()), t);
+        }
+    }
+}
+
+#[test]
+fn k_nearest_test() {
+    for (seed, kind) in [
+        CoordinateKind::Cube {
+            side_len: 0.05,
+            align: CellAlign::Corner,
+        },
+        CoordinateKind::Tri {
+            side_len: TriCoord::height_to_side_len(0.05),
+        },
+        CoordinateKind::Hex {
+            circumradius: 0.05,
+            orientation: HexOrientation::PointyTop,
+        },
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        let pts = random_points(500, seed as u64);
+        let sh = SpatialHash::from_iter_with(kind, pts.iter().map(|&[x, y]| (x, y, [x, y])));
+        for q in random_points(20, 100 + seed as u64) {
+            for k in [0, 1, 5, 40] {
+                let found = sh.k_nearest(q[0], q[1], k, |&p| dist(p, q));
+                let mut brute = pts.iter().map(|&p| dist(p, q)).collect::<Vec<_>>();
+                brute.sort_by(f32::total_cmp);
+                brute.truncate(k);
+                let found = found.into_iter().map(|&p| dist(p, q)).collect::<Vec<_>>();
+                assert_eq!(found, brute, "{kind:?} {q:?} {k}");
+            }
+        }
+    }
+
+    let mut sh = SpatialHash::cube(0.1);
+    assert!(sh.k_nearest(0., 0., 3, |_: &()| 0.).is_empty());
+    sh.add(0.9, 0.9, ());
+    sh.add(-0.9, 0.2, ());
+    assert_eq!(sh.k_nearest(0., 0., 3, |_| 0.).len(), 2);
+}
+
+#[test]
+fn add_reporting_test() {
+    let mut sh = SpatialHash::hex(0.1);
+    let r = sh.add_reporting(0.5, 0.5, 0);
+    assert!(r.was_new_cell);
+    assert_eq!(r.prior_count, 0);
+    let r = sh.add_reporting(0.5, 0.5, 1);
+    assert!(!r.was_new_cell);
+    assert_eq!(r.prior_count, 1);
+    assert_eq!(r.slice, &[0, 1]);
+    let r = sh.add_reporting(0.5, 0.5, 2);
+    assert_eq!(r.prior_count, 2);
+    let r = sh.add_reporting(-0.5, 0.5, 3);
+    assert!(r.was_new_cell);
+    assert_eq!(r.slice, &[3]);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_round_trip_test() {
+    let pts = random_points(100, 7);
+    let sh = SpatialHash::from_iter_with(
+        CoordinateKind::Hex {
+            circumradius: 0.1,
+            orientation: HexOrientation::PointyTop,
+        },
+        pts.iter().enumerate().map(|(i, &[x, y])| (x, y, i)),
+    );
+    let json = serde_json::to_string(&sh).unwrap();
+    let de: SpatialHash<usize> = serde_json::from_str(&json).unwrap();
+    assert_eq!(de.kind, sh.kind);
+    assert_eq!(de.len(), sh.len());
+    for [x, y] in pts {
+        assert_eq!(de.query(x, y), sh.query(x, y));
+    }
+
+    let coord = TriCoord::new(0.4, 0.2, 0.1);
+    let json = serde_json::to_string(&coord).unwrap();
+    assert_eq!(serde_json::from_str::<TriCoord<i32>>(&json).unwrap(), coord);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_config_round_trip_test() {
+    let mut sh = SpatialHash::cube(1.)
+        .with_wrap(Some([4., 4.]))
+        .with_fold(Fold::MultiplyShift)
+        .with_world_bounds(Some([4., 3.]));
+    sh.add(0.5, 0.5, 1);
+    assert_eq!(sh.query(4.5, 0.5), &[1]);
+    let json = serde_json::to_string(&sh).unwrap();
+    let de: SpatialHash<i32> = serde_json::from_str(&json).unwrap();
+    assert_eq!(de.wrap(), Some([4., 4.]));
+    assert_eq!(de.fold(), Fold::MultiplyShift);
+    assert_eq!(de.world_bounds(), Some([4., 3.]));
+    assert_eq!(de.query(4.5, 0.5), &[1]);
+
+    // hashes serialized without the configuration use the defaults.
+    let old = r#"{"kind":{"Cube":{"side_len":1.0}},"bins":[[[0,0],[1]]]}"#;
+    let de: SpatialHash<i32> = serde_json::from_str(old).unwrap();
+    assert_eq!((de.wrap(), de.fold()), (None, Fold::Modulo));
+    assert_eq!(
+        de.kind,
+        CoordinateKind::Cube {
+            side_len: 1.,
+            align: CellAlign::Corner
+        }
+    );
+    assert_eq!(de.query(0.5, 0.5), &[1]);
+
+    // hexes serialized before they had an orientation are pointy top.
+    let old_hex = r#"{"kind":{"Hex":{"circumradius":0.5}},"bins":[[[0,0],[2]]]}"#;
+    let de: SpatialHash<i32> = serde_json::from_str(old_hex).unwrap();
+    assert_eq!(
+        de.kind,
+        CoordinateKind::Hex {
+            circumradius: 0.5,
+            orientation: HexOrientation::PointyTop
+        }
+    );
+    assert_eq!(de.query(0., 0.), &[2]);
+
+    // a wrap which doesn't fit the kind is an error rather than a panic.
+    let bad = old.replace(r#""bins""#, r#""wrap":[2.5,4.0],"bins""#);
+    assert!(serde_json::from_str::<SpatialHash<i32>>(&bad).is_err());
+}
+
+#[test]
+fn segment_cells_test() {
+    let sh = SpatialHash::<()>::cube(0.1);
+    for (start, end) in [
+        ([0.05, 0.05], [0.93, 0.05]),
+        ([0.31, 0.72], [0.31, -0.4]),
+        ([-0.2, 0.13], [0.77, 0.58]),
+    ] {
+        let cells = sh.segment_cells(start, end).unwrap().collect::<Vec<_>>();
+        let total: f32 = cells.iter().map(|&(_, a, b)| dist(a, b)).sum();
+        assert!((total - dist(start, end)).abs() < 1e-5, "{total}");
+        assert_eq!(cells[0].0, sh.idx(start[0], start[1]).1);
+        assert_eq!(cells.last().unwrap().0, sh.idx(end[0], end[1]).1);
+        for (key, a, b) in cells {
+            let mid = [(a[0] + b[0]) / 2., (a[1] + b[1]) / 2.];
+            assert_eq!(sh.idx(mid[0], mid[1]).1, key);
+        }
+    }
+    assert_eq!(
+        sh.segment_cells([0.05, 0.05], [0.93, 0.05])
+            .unwrap()
+            .count(),
+        10
+    );
+
+    let rect = SpatialHash::<()>::new(CoordinateKind::Rect {
+        w: 0.1,
+        h: 0.25,
+        align: CellAlign::Center,
+    });
+    let (start, end) = ([-0.2, 0.13], [0.77, 0.58]);
+    let cells = rect.segment_cells(start, end).unwrap().collect::<Vec<_>>();
+    let total: f32 = cells.iter().map(|&(_, a, b)| dist(a, b)).sum();
+    assert!((total - dist(start, end)).abs() < 1e-5, "{total}");
+    for (key, a, b) in cells {
+        let mid = [(a[0] + b[0]) / 2., (a[1] + b[1]) / 2.];
+        assert_eq!(rect.idx(mid[0], mid[1]).1, key);
+    }
+
+    assert!(SpatialHash::<()>::hex(0.1)
+        .segment_cells(start, end)
+        .is_none());
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn par_build_test() {
+    use rayon::prelude::*;
+    let pts = random_points(10_000, 3);
+    let kind = CoordinateKind::Tri { side_len: 0.05 };
+    let items = || pts.iter().enumerate().map(|(i, &[x, y])| (x, y, i));
+    let serial = SpatialHash::from_iter_with(kind, items());
+    let par = SpatialHash::par_build(kind, items().collect::<Vec<_>>().into_par_iter());
+    assert_eq!(par.len(), serial.len());
+    for [x, y] in pts {
+        assert_eq!(par.query(x, y), serial.query(x, y));
+    }
+}
+
+#[test]
+fn fold_hash_test() {
+    let hashes = (0..1000u64).map(|i| i.wrapping_mul(0x9E3779B97F4A7C15));
+    for h in hashes {
+        assert_eq!(crate::fold_hash::<256>(h), (h as usize) % 256);
+        assert_eq!(crate::fold_hash::<64>(h), (h as usize) % 64);
+        assert_eq!(crate::fold_hash::<100>(h), (h as usize) % 100);
+    }
+}
+
+#[test]
+fn entry_test() {
+    let mut sh = SpatialHash::tri_h(0.1);
+    let e = sh.entry(0.42, 0.17);
+    assert!(e.is_empty());
+    e.push(1);
+    e.push(2);
+    assert_eq!(sh.query(0.42, 0.17), &[1, 2]);
+    sh.add(0.42, 0.17, 3);
+    assert_eq!(sh.entry(0.42, 0.17).len(), 3);
+}
+
+#[test]
+fn shrink_buckets_test() {
+    let pts = random_points(40, 11);
+    let mut sh = SpatialHash::cube(0.01).resize_buckets::<1024>();
+    for (i, &[x, y]) in pts.iter().enumerate() {
+        sh.add(x, y, i);
+    }
+    assert!(sh.load_factor() < 0.05);
+    let small: SpatialHash<usize, 64> = sh.clone().shrink_buckets();
+    assert_eq!(small.len(), sh.len());
+    for [x, y] in pts {
+        assert_eq!(small.query(x, y), sh.query(x, y));
+    }
+}
+
+#[test]
+fn retain_test() {
+    let mut sh = SpatialHash::hex(0.1);
+    sh.add(0.5, 0.5, (0, true));
+    sh.add(0.5, 0.5, (1, false));
+    sh.add(-0.5, 0.5, (2, true));
+    sh.add(0.5, -0.5, (3, false));
+    sh.retain(|&(_, keep)| keep);
+    assert_eq!(sh.len(), 2);
+    assert_eq!(sh.query(0.5, 0.5), &[(0, true)]);
+    assert!(sh.query(0.5, -0.5).is_empty());
+    assert_eq!(sh.data.iter().map(|bins| bins.len()).sum::<usize>(), 2);
+}
+
+#[test]
+fn nearest_centroid_test() {
+    let mut cube = SpatialHash::cube(0.1);
+    let [x, y] = cube.nearest_centroid(0.12, 0.37);
+    assert!(
+        (x - 0.15).abs() < 1e-5 && (y - 0.35).abs() < 1e-5,
+        "{x} {y}"
+    );
+    assert_eq!(cube.nearest_occupied_centroid(0.12, 0.37), None);
+    cube.add(0.81, 0.52, ());
+    cube.add(-0.92, 0.11, ());
+    let [x, y] = cube.nearest_occupied_centroid(0.12, 0.37).unwrap();
+    assert!(
+        (x - 0.85).abs() < 1e-5 && (y - 0.55).abs() < 1e-5,
+        "{x} {y}"
     );
+
+    let mut hex = SpatialHash::hex(0.1);
+    let h = HexAxial { q: 2, r: 3 };
+    let [cx, cy] = h.to_euclidean(0.1);
+    let [x, y] = hex.nearest_centroid(cx + 0.02, cy - 0.03);
+    assert!((x - cx).abs() < 1e-5 && (y - cy).abs() < 1e-5);
+    assert_eq!(hex.nearest_occupied_centroid(0., 0.), None);
+    hex.add(cx, cy, ());
+    hex.add(-cx * 2., -cy * 2., ());
+    let [x, y] = hex.nearest_occupied_centroid(0., 0.).unwrap();
+    assert!((x - cx).abs() < 1e-5 && (y - cy).abs() < 1e-5);
+}
+
+#[test]
+fn tri_centroid_test() {
+    let side_len = 0.1;
+    for [x, y] in random_points(200, 5) {
+        let t = TriCoord::new(x, y, side_len);
+        let [cx, cy] = t.to_euclidean(side_len);
+        assert_eq!(TriCoord::new(cx, cy, side_len), t);
+    }
+}
+
+#[test]
+fn rebuild_from_test() {
+    let pts = random_points(300, 9);
+    let mut sh = SpatialHash::from_iter_with(
+        CoordinateKind::Cube {
+            side_len: 0.1,
+            align: CellAlign::Corner,
+        },
+        pts.iter().map(|&[x, y]| (x, y, [x, y])),
+    );
+    let hex = CoordinateKind::Hex {
+        circumradius: 0.07,
+        orientation: HexOrientation::PointyTop,
+    };
+    sh.rebuild_from(hex, |&p| p);
+    let expected = SpatialHash::from_iter_with(hex, pts.iter().map(|&[x, y]| (x, y, [x, y])));
+    assert_eq!(sh.kind, hex);
+    assert_eq!(sh.len(), pts.len());
+    for [x, y] in pts {
+        assert!(sh.query(x, y).contains(&[x, y]));
+        assert_eq!(sh.query(x, y).len(), expected.query(x, y).len());
+    }
+}
+
+#[test]
+fn wu_coverage_test() {
+    for (s @ [x0, y0], e @ [x1, y1]) in [
+        ([0.3f32, 0.2], [9.7, 3.1]),
+        ([12., -4.], [1., 2.5]),
+        ([2.2, 1.], [3.1, 8.4]),
+    ] {
+        let steep = (y1 - y0).abs() > (x1 - x0).abs();
+        let cells = lines::wu_coverage(s, e).collect::<Vec<_>>();
+        for pair in cells.chunks(2) {
+            let [(a, ca), (b, cb)] = [pair[0], pair[1]];
+            assert!((ca + cb - 1.).abs() < 1e-5);
+            // distance of each pixel center to the line, measured across the major axis
+            let off = |[x, y]: [i32; 2]| {
+                let [major, minor] = if steep { [y, x] } else { [x, y] };
+                let [m0, n0, m1, n1] = if steep {
+                    [y0, x0, y1, x1]
+                } else {
+                    [x0, y0, x1, y1]
+                };
+                (minor as f32 - (n0 + (n1 - n0) / (m1 - m0) * (major as f32 - m0))).abs()
+            };
+            if ca > cb + 1e-3 {
+                assert!(off(a) <= off(b) + 1e-4);
+            } else if cb > ca + 1e-3 {
+                assert!(off(b) <= off(a) + 1e-4);
+            }
+        }
+    }
+}
+
+#[test]
+fn query_line_aa_test() {
+    let mut sh = SpatialHash::cube(1.);
+    sh.add(2.5, 2.5, 'a');
+    sh.add(8.5, 0.5, 'b');
+    let hits = sh
+        .query_line_aa([0.5, 0.5], [4.5, 4.5])
+        .unwrap()
+        .collect::<Vec<_>>();
+    assert!(hits.iter().any(|&(cov, v)| v == ['a'] && cov > 0.99));
+    assert!(hits.iter().all(|&(_, v)| v != ['b']));
+
+    // both ends in one column only yield its cells once.
+    let mut sh = SpatialHash::cube(1.);
+    sh.add(0.5, 0.5, 'a');
+    let hits = sh
+        .query_line_aa([0.4, 0.5], [0.6, 0.5])
+        .unwrap()
+        .collect::<Vec<_>>();
+    assert_eq!(hits, [(1., &['a'][..])]);
+    assert_eq!(lines::wu_coverage([0.2, 0.1], [0.3, 0.4]).count(), 2);
+
+    let mut rect = SpatialHash::new(CoordinateKind::Rect {
+        w: 2.,
+        h: 0.5,
+        align: CellAlign::Corner,
+    });
+    rect.add(5., 0.25, 'r');
+    let hits = rect
+        .query_line_aa([1., 0.25], [9., 0.25])
+        .unwrap()
+        .collect::<Vec<_>>();
+    assert_eq!(hits, [(1., &['r'][..])]);
+    assert!(SpatialHash::<()>::hex(1.)
+        .query_line_aa([0., 0.], [1., 1.])
+        .is_none());
+}
+
+#[test]
+fn adjacent_3d_test() {
+    let mut sh = SpatialHash3::cube(0.1);
+    let freq = 32;
+    for i in 0..freq {
+        let i = (i as f32) / (freq as f32);
+        for j in 0..freq {
+            let j = (j as f32) / (freq as f32);
+            for k in 0..freq {
+                sh.add(i, j, k as f32 / freq as f32, ());
+            }
+        }
+    }
+    assert_eq!(sh.len(), freq * freq * freq);
+    // 3.2 samples per cell along each axis, so each cell holds 3 or 4 per axis.
+    let center = sh.query(0.5, 0.5, 0.5).len();
+    assert!((27..=64).contains(&center), "{center}");
+    assert_eq!(sh.query_one_ring(0.55, 0.55, 0.55).count(), 27);
+    assert_eq!(sh.query_one_ring(0.01, 0.01, 0.01).count(), 8);
+}
+
+#[test]
+fn extend_from_test() {
+    let mut shared = SpatialHash::tri_h(0.1);
+    shared.add(0.3, 0.3, "static");
+    let mut local = SpatialHash::tri_h(0.1);
+    local.add(0.3, 0.3, "dynamic");
+    local.add(0.9, 0.1, "dynamic");
+    local.extend_from(&shared);
+    assert_eq!(local.query(0.3, 0.3), &["dynamic", "static"]);
+    assert_eq!(local.len(), 3);
+    assert_eq!(shared.len(), 1);
+}
+
+#[test]
+fn flat_storage_test() {
+    let pts = random_points(500, 13);
+    for kind in all_kinds(0.05) {
+        let sharded = SpatialHash::from_iter_with(kind, pts.iter().map(|&[x, y]| (x, y, [x, y])));
+        let mut flat = FlatSpatialHash::flat(kind);
+        flat.extend(pts.iter().map(|&[x, y]| (x, y, [x, y])));
+        assert_eq!(flat.len(), sharded.len());
+        for [x, y] in pts.iter().copied() {
+            assert_eq!(flat.query(x, y), sharded.query(x, y));
+            let mut a = flat.query_one_ring(x, y).flatten().collect::<Vec<_>>();
+            let mut b = sharded.query_one_ring(x, y).flatten().collect::<Vec<_>>();
+            a.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            b.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            assert_eq!(a, b);
+        }
+    }
+}
+
+#[test]
+fn query_indexed_test() {
+    let mut sh = SpatialHash::cube(0.1);
+    for v in [5, 6, 7] {
+        sh.add(0.55, 0.55, v);
+    }
+    let (idx, key) = sh.idx(0.55, 0.55);
+    let bin = &sh.data[idx][&key];
+    for (i, v) in sh.query_indexed(0.55, 0.55) {
+        assert_eq!(&bin[i], v);
+    }
+    assert_eq!(sh.query_indexed(0.55, 0.55).count(), 3);
+}
+
+#[test]
+fn bins_test() {
+    let pts = random_points(100, 17);
+    let mut sh = SpatialHash::tri_h(0.1);
+    for &[x, y] in &pts {
+        sh.add(x, y, [x, y]);
+    }
+    sh.entry(5., 5.);
+    let mut count = 0;
+    for (idx, key, vals) in sh.bins() {
+        assert!(!vals.is_empty());
+        for &[x, y] in vals {
+            assert_eq!(sh.idx(x, y), (idx, key));
+        }
+        count += vals.len();
+    }
+    assert_eq!(count, pts.len());
+}
+
+#[test]
+fn cube_clipped_radius_test() {
+    let side = 1.;
+    let c = Euclidean::from_euclidean(0.5, 0.5, side);
+    assert_eq!(c.one_ring_clipped(0.5, 0.5, side, 0.4).count(), 0);
+    // edge neighbors are reachable, but corners are sqrt(0.5) away
+    assert_eq!(c.one_ring_clipped(0.5, 0.5, side, 0.6).count(), 4);
+    assert_eq!(c.one_ring_clipped(0.5, 0.5, side, 0.75).count(), 8);
+    // near a corner only the three neighbors around that corner are needed
+    let near = c.one_ring_clipped(0.1, 0.1, side, 0.2).collect::<Vec<_>>();
+    assert_eq!(near.len(), 3);
+    assert!(near.iter().all(|n| n.x <= 0 && n.y <= 0));
+    // with the radius equal to the cell size, everything must be checked
+    assert_eq!(c.one_ring_clipped(0.5, 0.5, side, side).count(), 8);
+}
+
+#[test]
+fn iter_mut_test() {
+    for kind in all_kinds(1.) {
+        let mut sh = SpatialHash::new(kind);
+        for (i, [x, y]) in random_points(200, 17).into_iter().enumerate() {
+            sh.add(x, y, i);
+        }
+        let before = sh.iter().map(|(p, v)| (p, v.to_vec())).collect::<Vec<_>>();
+        for (_, vals) in sh.iter_mut() {
+            vals.iter_mut().for_each(|v| *v *= 2);
+        }
+        let after = sh.iter().collect::<Vec<_>>();
+        assert_eq!(before.len(), after.len());
+        for ((p0, v0), (p1, v1)) in before.iter().zip(after) {
+            assert_eq!(*p0, p1);
+            assert!(v0.iter().zip(v1).all(|(a, b)| a * 2 == *b));
+        }
+    }
+}
+
+#[test]
+fn debug_grid_test() {
+    let mut sh = SpatialHash::cube(1.);
+    sh.add(1.5, 0.5, ());
+    let grid = sh.debug_grid(([0., 0.], [3., 2.]), 3, 2);
+    assert_eq!(grid, "...\n.1.\n");
+    assert_eq!(grid.chars().filter(|&c| c != '.' && c != '\n').count(), 1);
+
+    for _ in 0..12 {
+        sh.add(0.5, 1.5, ());
+    }
+    assert_eq!(sh.debug_grid(([0., 0.], [3., 2.]), 3, 2), "#..\n.1.\n");
+}
+
+#[test]
+fn cell_area_test() {
+    let root3 = (3.0f32).sqrt();
+    let close = |a: f32, b: f32| (a - b).abs() < 1e-5 * b.max(1.);
+    assert!(close(SpatialHash::<()>::cube(0.3).cell_area(), 0.09));
+    assert!(close(
+        SpatialHash::<()>::new(CoordinateKind::Rect {
+            w: 0.5,
+            h: 2.,
+            align: CellAlign::Corner,
+        })
+        .cell_area(),
+        1.
+    ));
+    assert!(close(
+        SpatialHash::<()>::hex(2.).cell_area(),
+        3. * root3 / 2. * 4.
+    ));
+    assert!(close(
+        SpatialHash::<()>::new(CoordinateKind::Tri { side_len: 2. }).cell_area(),
+        root3 / 4. * 4.
+    ));
+    assert!(close(HexAxial::<i32>::cell_area(1.), 3. * root3 / 2.));
+
+    // a hexagon is six triangles with the same side length.
+    assert!(close(
+        HexAxial::<i32>::cell_area(0.7),
+        6. * TriCoord::<i32>::cell_area(0.7)
+    ));
+}
+
+#[test]
+fn memory_bytes_test() {
+    let mut sh = SpatialHash::cube(0.05);
+    let empty = sh.memory_bytes();
+    assert_eq!(empty, sh.memory_report().buckets);
+    let mut prev = empty;
+    for [x, y] in random_points(300, 17) {
+        sh.add(x, y, [x, y]);
+        let bytes = sh.memory_bytes();
+        assert!(bytes >= prev);
+        prev = bytes;
+    }
+    assert!(prev > empty);
+    sh.clear();
+    assert_eq!(sh.memory_bytes(), empty);
+}
+
+#[test]
+fn query_stable_test() {
+    let mut sh = SpatialHash::cube(1.);
+    sh.add(0.5, 0.5, 0);
+    sh.add(1.5, 0.5, 1);
+    let (mut key, _) = sh.query_keyed(0.99, 0.5);
+    assert_eq!(key, [0, 0]);
+    for i in 0..20 {
+        let x = if i % 2 == 0 { 1.01 } else { 0.99 };
+        let (k, vals) = sh.query_stable(x, 0.5, key, 0.05);
+        assert_eq!(k, [0, 0]);
+        assert_eq!(vals, &[0]);
+        key = k;
+    }
+    // far enough past the boundary switches cells.
+    let (k, vals) = sh.query_stable(1.1, 0.5, key, 0.05);
+    assert_eq!((k, vals), ([1, 0], &[1][..]));
+    // and a stale key which is not a neighbor is ignored.
+    assert_eq!(sh.query_stable(0.5, 0.5, [5, 5], 0.05).0, [0, 0]);
+
+    let mut sh = SpatialHash::hex(1.);
+    sh.add(0., 0., ());
+    let apothem = (3.0f32).sqrt() / 2.;
+    let (key, _) = sh.query_keyed(apothem - 0.01, 0.);
+    assert_eq!(sh.query_stable(apothem + 0.01, 0., key, 0.05).0, key);
+    assert_ne!(sh.query_stable(apothem + 0.1, 0., key, 0.05).0, key);
+}
+
+#[test]
+fn remove_at_test() {
+    let mut sh = SpatialHash::cube(1.);
+    for i in 0..3 {
+        sh.add(0.5, 0.5, i);
+    }
+    let (idx, key) = sh.idx(0.5, 0.5);
+    assert_eq!(sh.remove_at(idx, key, 3), None);
+    assert_eq!(sh.remove_at(idx, [7, 7], 0), None);
+    assert_eq!(sh.remove_at(sh.data.len(), key, 0), None);
+
+    assert_eq!(sh.remove_at(idx, key, 1), Some(1));
+    assert_eq!(sh.query(0.5, 0.5), &[0, 2]);
+    assert_eq!(sh.remove_at(idx, key, 0), Some(0));
+    assert_eq!(sh.remove_at(idx, key, 0), Some(2));
+    assert!(!sh.data[idx].contains_key(&key));
+}
+
+#[test]
+fn debug_cells_test() {
+    let mut sh = SpatialHash::cube(0.5);
+    sh.add(0.75, -0.25, ());
+    // rows are v = 1, 0, -1, columns u = -1..=2.
+    assert_eq!(sh.debug_cells([-1, -1], [2, 1]), "....\n....\n..1.\n");
+    assert_eq!(sh.debug_cells([1, -1], [1, -1]), "1\n");
+    assert_eq!(sh.debug_cells([1, 1], [0, 0]), "");
+
+    let mut sh = SpatialHash::hex(1.);
+    sh.add(0.1, 0.1, ());
+    sh.add(0.2, 0., ());
+    assert_eq!(sh.debug_cells([0, 0], [0, 0]), "2\n");
+}
+
+#[test]
+fn hex_edge_midpoints_test() {
+    let circumradius = 0.7;
+    let apothem = circumradius * (3.0f32).sqrt() / 2.;
+    for [x, y] in random_points(20, 5) {
+        let h = HexAxial::from_euclidean(x * 10., y * 10., circumradius);
+        let c = h.to_euclidean(circumradius);
+        for (n, m) in h.one_ring().into_iter().zip(h.edge_midpoints(circumradius)) {
+            let nc = n.to_euclidean(circumradius);
+            assert!((dist(c, m) - apothem).abs() < 1e-4);
+            assert!((dist(nc, m) - apothem).abs() < 1e-4);
+            // the midpoint is on the boundary between both cells
+            let toward =
+                |[tx, ty]: [f32; 2]| [m[0] + (tx - m[0]) * 0.01, m[1] + (ty - m[1]) * 0.01];
+            assert!(h.contains(toward(c), circumradius));
+            assert!(!h.contains(toward(nc), circumradius));
+            assert!(n.contains(toward(nc), circumradius));
+        }
+    }
+}
+
+#[test]
+fn add_batch_test() {
+    let pts = random_points(500, 21);
+    let mut naive = SpatialHash::hex(0.05);
+    let mut batch = SpatialHash::hex(0.05).with_hasher(naive.state.clone());
+    for (i, &[x, y]) in pts.iter().enumerate() {
+        naive.add(x, y, i);
+    }
+    batch.add_batch(pts.iter().enumerate().map(|(i, &[x, y])| (x, y, i)));
+    let naive = naive.bins().collect::<Vec<_>>();
+    assert_eq!(naive, batch.bins().collect::<Vec<_>>());
+}
+
+#[test]
+fn layered_query_test() {
+    let mut pegs = SpatialHash::cube(1.);
+    let mut balls = SpatialHash::cube(1.);
+    pegs.add(0.5, 0.5, "peg");
+    pegs.add(5.5, 5.5, "far peg");
+    balls.add(1.5, 0.5, "ball");
+    let layers = LayeredQuery::new([&pegs, &balls]);
+    let mut found = layers
+        .query_one_ring([0.9, 0.5])
+        .copied()
+        .collect::<Vec<_>>();
+    found.sort();
+    assert_eq!(found, ["ball", "peg"]);
+}
+
+#[test]
+fn hex_round_property_test() {
+    let check = |q: f32, r: f32| {
+        let f = HexAxial { q, r };
+        let h = f.round();
+        assert_eq!(h.q + h.r + h.s(), 0);
+        // the cube rounding never moves a coordinate by more than one cell
+        assert!((h.q as f32 - q).abs() <= 1. && (h.r as f32 - r).abs() <= 1.);
+        assert!((h.s() as f32 - f.s()).abs() <= 1.);
+        let p = f.to_euclidean();
+        let p = [p.x, p.y];
+        let d = dist(p, h.to_euclidean(1.));
+        for n in h.one_ring() {
+            assert!(
+                d <= dist(p, n.to_euclidean(1.)) + 1e-4,
+                "{f:?} rounded to {h:?} but {n:?} is closer"
+            );
+        }
+    };
+    for [q, r] in random_points(5000, 34) {
+        check(q * 40. - 20., r * 40. - 20.);
+    }
+    // points just either side of every edge, where the wrong branch would be picked
+    let h = HexAxial { q: 3, r: -2 };
+    for m in h.edge_midpoints(1.) {
+        let c = h.to_euclidean(1.);
+        for t in [0.999, 1.001] {
+            let [x, y] = [c[0] + (m[0] - c[0]) * t, c[1] + (m[1] - c[1]) * t];
+            let root3 = (3.0f32).sqrt();
+            check(x * root3 / 3. - y / 3., 2. * y / 3.);
+        }
+    }
+}
+
+#[test]
+fn matched_params_test() {
+    let radius = 0.3;
+    let (cube, hex, tri) = matched_params(radius);
+    for kind in [
+        CoordinateKind::Cube {
+            side_len: cube,
+            align: CellAlign::Corner,
+        },
+        CoordinateKind::Hex {
+            circumradius: hex,
+            orientation: HexOrientation::PointyTop,
+        },
+        CoordinateKind::Tri { side_len: tri },
+    ] {
+        for [x, y] in random_points(20, 35) {
+            let key = kind.key(x * 5., y * 5.);
+            let [cx, cy] = kind.centroid(key);
+            // the disc fits inside the cell, but only just
+            let on_circle = |r: f32| {
+                (0..64).all(|i| {
+                    let a = i as f32 / 64. * std::f32::consts::TAU;
+                    kind.key(cx + r * a.cos(), cy + r * a.sin()) == key
+                })
+            };
+            assert!(on_circle(radius * 0.999), "{kind:?}");
+            assert!(!on_circle(radius * 1.01), "{kind:?}");
+        }
+    }
+}
+
+#[test]
+fn tri_try_new_test() {
+    let root3 = (3.0f32).sqrt();
+    let side_len = 1.;
+    let mut errors = 0;
+    // lattice vertices are on the edge of six triangles, where rounding error is most likely
+    for i in -40..40 {
+        for j in -40..40 {
+            let x = (i as f32 + 0.5 * j as f32) * side_len;
+            let y = j as f32 * root3 / 2. * side_len;
+            let clamped = TriCoord::new_clamped(x, y, side_len);
+            let sum = clamped.s + clamped.t + clamped.u;
+            assert!(sum == 1 || sum == 2);
+            // the vertex is a corner of the clamped triangle
+            let c = clamped.to_euclidean(side_len);
+            assert!((dist(c, [x, y]) - side_len / root3).abs() < 1e-3);
+            match TriCoord::try_new(x, y, side_len) {
+                Ok(t) => assert_eq!(t, clamped),
+                Err(e) => {
+                    let TriCoord { s, t, u } = e.coord;
+                    assert!(s + t + u == 0 || s + t + u == 3);
+                    errors += 1;
+                }
+            }
+        }
+    }
+    assert!(errors > 0);
+    assert!(TriCoord::try_new(0.4, 0.2, 0.1).is_ok());
+}
+
+#[test]
+fn drain_test() {
+    for kind in all_kinds(0.1) {
+        let pts = random_points(300, 37);
+        let mut sh = SpatialHash::from_iter_with(kind, pts.iter().map(|&[x, y]| (x, y, [x, y])));
+        let expected = sh
+            .iter()
+            .flat_map(|(p, vals)| vals.iter().map(move |&v| (p, v)))
+            .collect::<Vec<_>>();
+        let drained = sh.drain().collect::<Vec<_>>();
+        assert!(sh.is_empty());
+        assert_eq!(drained.len(), pts.len());
+        assert_eq!(drained, expected);
+        // the hash is still usable afterwards
+        sh.add(0.5, 0.5, [0.5, 0.5]);
+        assert_eq!(sh.len(), 1);
+    }
+}
+
+#[test]
+fn remove_reporting_test() {
+    let mut sh = SpatialHash::tri_h(0.1);
+    sh.add(0.32, 0.51, 1);
+    sh.add(0.32, 0.51, 2);
+    assert_eq!(sh.remove_reporting(0.32, 0.51, |&v| v == 3), (None, 2));
+    assert_eq!(sh.remove_reporting(0.32, 0.51, |&v| v == 1), (Some(1), 1));
+    assert_eq!(sh.bins().count(), 1);
+    assert_eq!(sh.remove_reporting(0.32, 0.51, |&v| v == 2), (Some(2), 0));
+    // the empty cell is pruned rather than left behind
+    assert_eq!(sh.data.iter().map(|b| b.len()).sum::<usize>(), 0);
+    assert_eq!(sh.remove(0.32, 0.51, |_| true), None);
+}
+
+#[cfg(feature = "rand")]
+#[test]
+fn sample_in_occupied_test() {
+    use rand::SeedableRng;
+    let mut rng = rand::rngs::StdRng::seed_from_u64(39);
+    for kind in all_kinds(0.1) {
+        let mut sh = SpatialHash::new(kind);
+        assert_eq!(sh.sample_in_occupied(&mut rng), None);
+        for [x, y] in random_points(10, 39) {
+            sh.add(x, y, ());
+        }
+        let mut cells = std::collections::HashSet::new();
+        for _ in 0..2000 {
+            let [x, y] = sh.sample_in_occupied(&mut rng).unwrap();
+            assert!(!sh.query(x, y).is_empty(), "{kind:?} sampled {x} {y}");
+            cells.insert(sh.key(x, y));
+        }
+        // every occupied cell is reached
+        assert_eq!(cells.len(), sh.bins().count());
+    }
+}
+
+#[test]
+fn query_n_ring_test() {
+    let cube = |n: usize| (2 * n + 1) * (2 * n + 1);
+    let hex = |n: usize| 1 + 3 * n * (n + 1);
+    // each ring of triangles sharing a vertex adds 12 more than the previous one
+    let tri = |n: usize| 1 + 6 * n * (n + 1);
+    let expected: [&dyn Fn(usize) -> usize; 3] = [&cube, &hex, &tri];
+    for (kind, expected) in all_kinds(0.01).into_iter().zip(expected) {
+        // fill every cell in the area with one item
+        let mut sh = SpatialHash::new(kind);
+        for i in 0..400 {
+            for j in 0..400 {
+                let [x, y] = [i as f32 / 400., j as f32 / 400.];
+                if sh.query(x, y).is_empty() {
+                    sh.add(x, y, [x, y]);
+                }
+            }
+        }
+        for n in 0..5 {
+            let bins = sh.query_n_ring(0.5, 0.5, n).collect::<Vec<_>>();
+            assert_eq!(bins.len(), expected(n as usize), "{kind:?} {n}");
+            let mut items = bins.iter().map(|b| b[0]).collect::<Vec<_>>();
+            items.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            items.dedup();
+            assert_eq!(items.len(), bins.len());
+        }
+        assert_eq!(
+            sh.query_n_ring(0.5, 0.5, 0).collect::<Vec<_>>(),
+            [sh.query(0.5, 0.5)]
+        );
+        // query_one_ring is the first ring, less any neighbors that it clips
+        let n_ring = sh.query_n_ring(0.5, 0.5, 1).collect::<Vec<_>>();
+        assert!(sh.query_one_ring(0.5, 0.5).all(|b| n_ring.contains(&b)));
+        assert_eq!(n_ring.len(), expected(1));
+    }
+}
+
+#[test]
+fn difference_test() {
+    let pts = random_points(100, 41);
+    let a = SpatialHash::from_iter_with(
+        CoordinateKind::Hex {
+            circumradius: 0.05,
+            orientation: HexOrientation::PointyTop,
+        },
+        pts.iter().map(|&[x, y]| (x, y, ())),
+    );
+    let mut b = SpatialHash::from_iter_with(
+        CoordinateKind::Hex {
+            circumradius: 0.05,
+            orientation: HexOrientation::PointyTop,
+        },
+        pts.iter().map(|&[x, y]| (x, y, ())),
+    );
+    assert_eq!(a.difference(&b).count(), 0);
+    let [x, y] = pts[0];
+    let expected = a.query(x, y).len();
+    while b.remove(x, y, |_| true).is_some() {}
+    // an empty bin left behind counts as unoccupied as well
+    b.entry(x, y);
+    let diff = a.difference(&b).collect::<Vec<_>>();
+    assert_eq!(diff.len(), 1);
+    let ([cx, cy], vals) = diff[0];
+    assert!(a.same_bin(cx, cy, x, y));
+    assert_eq!(vals.len(), expected);
+    assert_eq!(b.difference(&a).count(), 0);
+}
+
+#[test]
+fn simple_hash_test() {
+    use crate::hash::{SimpleHash, SimpleHashBuilder};
+    use std::hash::{BuildHasher, Hasher};
+    let b = SimpleHashBuilder::default();
+    // more than two words and raw bytes are both accepted
+    let mut h = SimpleHash::default();
+    h.write_i32(1);
+    h.write_i32(2);
+    h.write_i32(3);
+    h.write_i32(4);
+    h.write(b"not a multiple of eight");
+    assert_ne!(h.finish(), SimpleHash::default().finish());
+    assert_ne!(
+        b.hash_one(Euclidean { x: 1, y: 2 }),
+        b.hash_one(Euclidean { x: 2, y: 1 })
+    );
+
+    // a dense grid of keys is spread over the buckets about as well as random
+    let mut sh = SpatialHash::cube(1.).with_hasher(SimpleHashBuilder::default());
+    for i in 0..64 {
+        for j in 0..64 {
+            sh.add(i as f32, j as f32, ());
+        }
+    }
+    let counts = sh.data.iter().map(|b| b.len()).collect::<Vec<_>>();
+    let mean = sh.len() / 256;
+    assert!(counts.iter().all(|&c| c < mean * 3 && c > mean / 4));
+
+    // switching hashers keeps every item queryable
+    let sh = sh.with_hasher(std::collections::hash_map::RandomState::new());
+    assert!((0..64).all(|i| sh.query(i as f32, 63.).len() == 1));
+}
+
+#[test]
+fn query_one_ring_center_first_test() {
+    for kind in all_kinds(0.1) {
+        let mut sh = SpatialHash::new(kind);
+        for [x, y] in random_points(500, 43) {
+            sh.add(x, y, [x, y]);
+        }
+        for [x, y] in random_points(50, 44) {
+            let first = sh.query_one_ring(x, y).next();
+            if !sh.query(x, y).is_empty() {
+                assert_eq!(first, Some(sh.query(x, y)));
+            }
+            // the center is never repeated later on
+            let center = sh.query(x, y);
+            assert!(sh.query_one_ring(x, y).skip(1).all(|b| b != center));
+        }
+    }
+
+    let mut sh = SpatialHash3::cube(0.1);
+    let pts = random_points(500, 43);
+    for (&[x, y], &[z, _]) in pts.iter().zip(pts.iter().rev()) {
+        sh.add(x, y, z, [x, y, z]);
+    }
+    for [x, y] in random_points(50, 44) {
+        let center = sh.query(x, y, 0.5);
+        if !center.is_empty() {
+            assert_eq!(sh.query_one_ring(x, y, 0.5).next(), Some(center));
+        }
+        assert!(sh.query_one_ring(x, y, 0.5).skip(1).all(|b| b != center));
+    }
+}
+
+#[test]
+fn capacity_hint_test() {
+    let mut sh = SpatialHash::hex(0.1);
+    sh.add_with_capacity_hint(0.5, 0.5, 0, 32);
+    assert!(sh.query(0.5, 0.5).len() == 1 && sh.entry(0.5, 0.5).capacity() >= 32);
+    let ptr = sh.query(0.5, 0.5).as_ptr();
+    for i in 1..32 {
+        sh.add(0.5, 0.5, i);
+    }
+    // filling up to the hint never reallocates
+    assert_eq!(sh.query(0.5, 0.5).as_ptr(), ptr);
+
+    // the hint does not apply to bins which already exist
+    sh.add(2.5, 2.5, 0);
+    sh.add_with_capacity_hint(2.5, 2.5, 1, 1000);
+    assert!(sh.entry(2.5, 2.5).capacity() < 1000);
+    let mut flat = FlatSpatialHash::flat(CoordinateKind::Cube {
+        side_len: 1.,
+        align: CellAlign::Corner,
+    });
+    flat.add_with_capacity_hint(0.5, 0.5, (), 8);
+    assert!(flat.entry(0.5, 0.5).capacity() >= 8);
+}
+
+#[test]
+fn snapshot_restore_test() {
+    let pts = random_points(300, 45);
+    let mut sh = SpatialHash::from_iter_with(
+        CoordinateKind::Tri { side_len: 0.05 },
+        pts.iter().map(|&[x, y]| (x, y, [x, y])),
+    );
+    let before = sh
+        .bins()
+        .map(|(i, k, v)| (i, k, v.to_vec()))
+        .collect::<Vec<_>>();
+    let snap = sh.snapshot();
+    assert_eq!(snap.len(), pts.len());
+
+    for [x, y] in random_points(50, 46) {
+        sh.add(x + 2., y, [x, y]);
+    }
+    sh.retain(|&[x, _]| x > 0.5);
+    sh.rebuild_from(
+        CoordinateKind::Cube {
+            side_len: 0.1,
+            align: CellAlign::Corner,
+        },
+        |&p| p,
+    );
+
+    sh.restore(&snap);
+    assert_eq!(sh.kind, CoordinateKind::Tri { side_len: 0.05 });
+    let after = sh
+        .bins()
+        .map(|(i, k, v)| (i, k, v.to_vec()))
+        .collect::<Vec<_>>();
+    assert_eq!(before, after);
+    assert_eq!(sh.data.iter().map(|b| b.len()).sum::<usize>(), before.len());
+    for [x, y] in pts {
+        assert!(sh.query(x, y).contains(&[x, y]));
+    }
+}
+
+#[test]
+fn bin_for_coord_test() {
+    let cube = SpatialHash::<()>::cube(0.1);
+    let hex = SpatialHash::<()>::hex(0.1);
+    let tri = SpatialHash::<()>::new(CoordinateKind::Tri { side_len: 0.1 });
+    for [x, y] in random_points(100, 46) {
+        let c = Euclidean::from_euclidean(x, y, 0.1);
+        assert_eq!(cube.idx(x, y), (cube.bin_for_coord(c), [c.x, c.y]));
+        let c = HexAxial::from_euclidean(x, y, 0.1);
+        assert_eq!(hex.idx(x, y), (hex.bin_for_coord(c), [c.q, c.r]));
+        let c = TriCoord::from_euclidean(x, y, 0.1);
+        assert_eq!(tri.idx(x, y), (tri.bin_for_coord(c), c.canon2d()));
+    }
+}
+
+#[test]
+fn bin_for_coord_all_kinds_test() {
+    for align in [CellAlign::Corner, CellAlign::Center] {
+        let cube = SpatialHash::<()>::new(CoordinateKind::Cube {
+            side_len: 0.1,
+            align,
+        });
+        let rect = SpatialHash::<()>::new(CoordinateKind::Rect {
+            w: 0.1,
+            h: 0.3,
+            align,
+        });
+        for [x, y] in random_points(100, 47) {
+            let c = Euclidean::from_euclidean_aligned(x, y, 0.1, 0.1, align);
+            assert_eq!(cube.idx(x, y), (cube.bin_for_coord(c), [c.x, c.y]));
+            let c = Euclidean::from_euclidean_aligned(x, y, 0.1, 0.3, align);
+            assert_eq!(rect.idx(x, y), (rect.bin_for_coord(c), [c.x, c.y]));
+        }
+    }
+    for orientation in [HexOrientation::PointyTop, HexOrientation::FlatTop] {
+        let hex = SpatialHash::<()>::new(CoordinateKind::Hex {
+            circumradius: 0.1,
+            orientation,
+        });
+        for [x, y] in random_points(100, 48) {
+            let c = HexAxial::from_euclidean_oriented(x, y, 0.1, orientation);
+            assert_eq!(hex.idx(x, y), (hex.bin_for_coord(c), [c.q, c.r]));
+        }
+    }
+    // a wrapped world needs the coordinate wrapped first.
+    let wrapped = SpatialHash::<()>::cube(0.25).with_wrap(Some([1., 1.]));
+    for [x, y] in random_points(100, 49) {
+        let [x, y] = [x * 3. - 1., y * 3. - 1.];
+        let c = Euclidean::from_euclidean(x, y, 0.25);
+        let c = Euclidean {
+            x: c.x.rem_euclid(4),
+            y: c.y.rem_euclid(4),
+        };
+        assert_eq!(wrapped.idx(x, y), (wrapped.bin_for_coord(c), [c.x, c.y]));
+    }
+}
+
+#[test]
+fn add_one_ring_clone_test() {
+    for kind in all_kinds(0.1) {
+        let mut sh = SpatialHash::new(kind);
+        let calls = std::cell::Cell::new(0);
+        sh.add_one_ring(0.5, 0.5, String::from("ball"), |_| {
+            calls.set(calls.get() + 1)
+        });
+        let bins = sh.query_n_ring(0.5, 0.5, 1).collect::<Vec<_>>();
+        assert_eq!(bins.len(), calls.get());
+        assert_eq!(bins.len(), sh.len());
+        assert!(bins.iter().all(|b| b == &[String::from("ball")]));
+    }
+}
+
+#[test]
+fn query_rect_test() {
+    for kind in all_kinds(0.07) {
+        let pts = random_points(1000, 48);
+        let sh = SpatialHash::from_iter_with(kind, pts.iter().map(|&[x, y]| (x, y, [x, y])));
+        let corners = random_points(40, 49);
+        for c in corners.chunks(2) {
+            let min = [c[0][0].min(c[1][0]), c[0][1].min(c[1][1])];
+            let max = [c[0][0].max(c[1][0]), c[0][1].max(c[1][1])];
+            let found = sh.query_rect(min, max).flatten().collect::<Vec<_>>();
+            let inside = pts
+                .iter()
+                .filter(|p| (0..2).all(|i| min[i] <= p[i] && p[i] <= max[i]));
+            for p in inside {
+                assert!(found.contains(&p), "{kind:?} missed {p:?}");
+            }
+            // no bin is yielded twice
+            let mut unique = found.clone();
+            unique.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            unique.dedup();
+            assert_eq!(unique.len(), found.len());
+        }
+    }
+}
+
+#[test]
+fn rect_kind_test() {
+    let kind = CoordinateKind::Rect {
+        w: 1.,
+        h: 0.1,
+        align: CellAlign::Corner,
+    };
+    let mut sh = SpatialHash::new(kind);
+    sh.add(0.5, 0.05, 0);
+    sh.add(0.5, 0.15, 1);
+    sh.add(0.9, 0.05, 2);
+    // cells are short, so only a small change in y moves to another cell
+    assert!(!sh.same_bin(0.5, 0.05, 0.5, 0.15));
+    assert!(sh.same_bin(0.1, 0.05, 0.9, 0.05));
+    assert_eq!(sh.query(0.5, 0.05), &[0, 2]);
+    assert_eq!(sh.query_one_ring(0.5, 0.05).count(), 2);
+    assert_eq!(sh.query_one_ring(0.5, 0.35).count(), 0);
+    assert_eq!(
+        sh.query_one_ring(0.5, 0.25).flatten().collect::<Vec<_>>(),
+        [&1]
+    );
+    assert_eq!(sh.iter().map(|(p, _)| p).collect::<Vec<_>>().len(), 2);
+    for (p, vals) in sh.iter() {
+        assert_eq!(sh.query(p[0] + 0.5, p[1] + 0.05), vals);
+    }
+    sh.add_one_ring(0.5, 0.55, 3, |_| {});
+    assert_eq!(sh.len(), 12);
+    assert_eq!(sh.query(1.5, 0.65), &[3]);
+}
+
+#[test]
+fn query_one_ring_items_test() {
+    for kind in all_kinds(0.1) {
+        let pts = random_points(400, 50);
+        let sh = SpatialHash::from_iter_with(kind, pts.iter().map(|&[x, y]| (x, y, [x, y])));
+        for [x, y] in random_points(20, 51) {
+            let items = sh.query_one_ring_items(x, y).collect::<Vec<_>>();
+            let total = sh.query_one_ring(x, y).map(<[_]>::len).sum::<usize>();
+            assert_eq!(items.len(), total);
+            // the center is visited exactly once
+            for p in sh.query(x, y) {
+                assert_eq!(items.iter().filter(|&&i| i == p).count(), 1);
+            }
+        }
+    }
+}
+
+#[test]
+fn clipped_one_ring_test() {
+    for kind in all_kinds(0.1).into_iter().chain([CoordinateKind::Rect {
+        w: 0.1,
+        h: 0.05,
+        align: CellAlign::Corner,
+    }]) {
+        let pts = random_points(3000, 51);
+        let sh = SpatialHash::from_iter_with(kind, pts.iter().map(|&[x, y]| (x, y, [x, y])));
+        let radius = kind.ring_clearance(1);
+        let mut clipped = 0;
+        for q in random_points(200, 52) {
+            let found = sh.query_one_ring_items(q[0], q[1]).collect::<Vec<_>>();
+            // nothing within the guaranteed radius is lost
+            for p in pts.iter().filter(|&&p| dist(p, q) <= radius) {
+                assert!(found.contains(&p), "{kind:?} missed {p:?} near {q:?}");
+            }
+            clipped +=
+                sh.query_n_ring(q[0], q[1], 1).count() - sh.query_one_ring(q[0], q[1]).count();
+        }
+        assert!(clipped > 0, "{kind:?} never clipped");
+    }
+}
+
+#[test]
+fn clipping_preserves_results_near_centers_test() {
+    for kind in all_kinds(0.1).into_iter().chain([CoordinateKind::Rect {
+        w: 0.1,
+        h: 0.05,
+        align: CellAlign::Corner,
+    }]) {
+        let pts = random_points(3000, 53);
+        let sh = SpatialHash::from_iter_with(kind, pts.iter().map(|&[x, y]| (x, y, [x, y])));
+        let radius = kind.ring_clearance(1);
+        let (mut clipped_bins, mut unclipped_bins) = (0, 0);
+        for (i, q) in random_points(200, 54).into_iter().enumerate() {
+            let [cx, cy] = kind.centroid(kind.key(q[0], q[1]));
+            let jitter = (i as f32 * 0.7).sin() * radius * 0.05;
+            let q = [cx + jitter, cy - jitter];
+            let near = |items: Vec<&[f32; 2]>| {
+                let mut items = items
+                    .into_iter()
+                    .filter(|&&p| dist(p, q) <= radius)
+                    .copied()
+                    .collect::<Vec<_>>();
+                items.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                items
+            };
+            let clipped = near(sh.query_one_ring_items(q[0], q[1]).collect());
+            let unclipped = near(sh.query_n_ring(q[0], q[1], 1).flatten().collect());
+            assert_eq!(clipped, unclipped, "{kind:?} near {q:?}");
+            clipped_bins += sh.query_one_ring(q[0], q[1]).count();
+            unclipped_bins += sh.query_n_ring(q[0], q[1], 1).count();
+        }
+        // near the center of a square or hex every neighbor is within the clearance, so
+        // clipping only saves lookups for queries away from the center
+        assert!(clipped_bins <= unclipped_bins);
+    }
+}
+
+#[test]
+fn shrink_to_fit_test() {
+    let pts = random_points(10_000, 55);
+    let mut sh = SpatialHash::from_iter_with(
+        CoordinateKind::Cube {
+            side_len: 0.1,
+            align: CellAlign::Corner,
+        },
+        pts.iter().enumerate().map(|(i, &[x, y])| (x, y, i)),
+    );
+    let before = sh.memory_report();
+    sh.retain(|&i| i % 20 == 0);
+    // retain keeps the capacity of the bins which are left
+    assert!(sh.memory_report().items * 2 > before.items);
+    // leave an empty bin behind, as `entry` does for cells which are never filled
+    sh.entry(5., 5.);
+    let occupied = sh.bins().count();
+    assert_eq!(sh.load_factor() * 256., (occupied + 1) as f32);
+
+    sh.shrink_to_fit();
+    let after = sh.memory_report();
+    assert!(after.items * 10 < before.items, "{after:?} {before:?}");
+    assert_eq!(sh.len(), 500);
+    assert_eq!(sh.load_factor() * 256., occupied as f32);
+    assert!(sh.iter().all(|(_, vals)| vals.iter().all(|i| i % 20 == 0)));
+}
+
+#[test]
+fn rebuild_as_test() {
+    let pts = random_points(500, 56);
+    let cube = SpatialHash::from_iter_with(
+        CoordinateKind::Cube {
+            side_len: 0.05,
+            align: CellAlign::Corner,
+        },
+        pts.iter().enumerate().map(|(i, &[x, y])| (x, y, i)),
+    );
+    let hex = cube.rebuild_as(CoordinateKind::Hex {
+        circumradius: 0.03,
+        orientation: HexOrientation::PointyTop,
+    });
+    assert_eq!(
+        hex.kind,
+        CoordinateKind::Hex {
+            circumradius: 0.03,
+            orientation: HexOrientation::PointyTop
+        }
+    );
+    assert_eq!(hex.len(), cube.len());
+    for (i, &[x, y]) in pts.iter().enumerate() {
+        let [cx, cy] = euclidean_cell_center(cube.idx(x, y).1, 0.05);
+        assert!(hex.contains(cx, cy, |&j| j == i), "{i} not at {cx} {cy}");
+    }
+    let back = hex.rebuild_as(CoordinateKind::Cube {
+        side_len: 0.05,
+        align: CellAlign::Corner,
+    });
+    assert_eq!(back.len(), cube.len());
+}
+
+#[test]
+fn rebuild_as_keeps_config_test() {
+    let mut sh = SpatialHash::cube(1.)
+        .with_wrap(Some([4., 4.]))
+        .with_world_bounds(Some([4., 4.]))
+        .with_fold(Fold::MultiplyShift);
+    sh.add(0.5, 0.5, 1);
+    assert_eq!(sh.query(4.5, 0.5), [1]);
+    let same = sh.rebuild_as(sh.kind);
+    assert_eq!(same.wrap(), Some([4., 4.]));
+    assert_eq!(same.world_bounds(), Some([4., 4.]));
+    assert_eq!(same.fold(), Fold::MultiplyShift);
+    assert_eq!(same.query(4.5, 0.5), [1]);
+
+    let mut tracked = SpatialHash::cube(1.).with_wrap(Some([4., 4.]));
+    tracked.add_tracked(0.5, 0.5, 1);
+    let halved = tracked.rebuild_tracked_as(CoordinateKind::Cube {
+        side_len: 0.5,
+        align: CellAlign::Corner,
+    });
+    assert_eq!(halved.wrap(), Some([4., 4.]));
+    assert_eq!(halved.query_positions(4.5, 0.5).len(), 1);
+}
+
+#[test]
+fn count_in_radius_test() {
+    let pts = random_points(2000, 57);
+    for kind in [
+        CoordinateKind::Cube {
+            side_len: 0.05,
+            align: CellAlign::Corner,
+        },
+        CoordinateKind::Rect {
+            w: 0.05,
+            h: 0.02,
+            align: CellAlign::Corner,
+        },
+        CoordinateKind::Hex {
+            circumradius: 0.05,
+            orientation: HexOrientation::PointyTop,
+        },
+        CoordinateKind::Hex {
+            circumradius: 0.03,
+            orientation: HexOrientation::FlatTop,
+        },
+        CoordinateKind::Cube {
+            side_len: 0.07,
+            align: CellAlign::Center,
+        },
+        CoordinateKind::Tri { side_len: 0.05 },
+    ] {
+        let sh = SpatialHash::from_iter_with(kind, pts.iter().map(|&[x, y]| (x, y, [x, y])));
+        for q in random_points(20, 58) {
+            for rad in [0.01, 0.05, 0.12, 0.3, 3.] {
+                let expected = pts.iter().filter(|&&p| dist(p, q) <= rad).count();
+                assert_eq!(
+                    sh.count_in_radius(q[0], q[1], rad, |&p| p),
+                    expected,
+                    "{kind:?} {q:?} {rad}"
+                );
+            }
+        }
+        // these used to expand rings forever.
+        assert_eq!(
+            sh.count_in_radius(0.5, 0.5, f32::INFINITY, |&p| p),
+            pts.len()
+        );
+        assert_eq!(sh.count_in_radius(0.5, 0.5, f32::MAX, |&p| p), pts.len());
+        assert_eq!(sh.count_in_radius(0.5, 0.5, f32::NAN, |&p| p), 0);
+        assert_eq!(sh.count_in_radius(0.5, 0.5, -1., |&p| p), 0);
+    }
+
+    let mut sh = SpatialHash::cube(0.1).with_wrap(Some([1., 1.]));
+    for &[x, y] in &pts {
+        sh.add(x, y, [x, y]);
+    }
+    assert_eq!(
+        sh.count_in_radius(0.5, 0.5, f32::INFINITY, |&p| p),
+        pts.len()
+    );
+    let expected = pts.iter().filter(|&&p| dist(p, [0.5, 0.5]) <= 0.2).count();
+    assert_eq!(sh.count_in_radius(0.5, 0.5, 0.2, |&p| p), expected);
+}
+
+#[test]
+fn with_kind_test() {
+    fn check<const N: usize>(mut sh: SpatialHash<usize, N>) {
+        let pts = random_points(300, 59);
+        for (i, &[x, y]) in pts.iter().enumerate() {
+            sh.add(x, y, i);
+        }
+        assert_eq!(sh.len(), pts.len());
+        for (i, &[x, y]) in pts.iter().enumerate() {
+            assert!(sh.contains(x, y, |&j| j == i));
+            assert!(sh.query_one_ring_items(x, y).any(|&j| j == i));
+            assert!(sh.idx(x, y).0 < N);
+        }
+    }
+    check(SpatialHash::<usize, 1>::with_kind(CoordinateKind::Cube {
+        side_len: 0.1,
+        align: CellAlign::Corner,
+    }));
+    check(SpatialHash::<usize, 16>::with_hex(0.1));
+    check(SpatialHash::<usize, 1024>::with_tri_h(0.1));
+    check(SpatialHash::<usize, 16>::with_cube(0.1));
+}
+
+#[test]
+fn nearest_nonempty_test() {
+    for kind in all_kinds(0.1).into_iter().chain([CoordinateKind::Rect {
+        w: 0.1,
+        h: 0.05,
+        align: CellAlign::Corner,
+    }]) {
+        let mut sh = SpatialHash::new(kind);
+        assert!(sh.nearest_nonempty(0., 0.).is_none());
+        sh.add(0.9, -0.7, 'a');
+        let (c, items) = sh.nearest_nonempty(0., 0.).unwrap();
+        assert_eq!(items, &['a']);
+        assert_eq!(c, sh.nearest_centroid(0.9, -0.7));
+        // a closer cell, fewer rings out, is found instead
+        sh.add(0.3, 0.2, 'b');
+        assert_eq!(sh.nearest_nonempty(0., 0.).unwrap().1, &['b']);
+    }
+}
+
+#[test]
+fn nearest_nonempty_wrap_test() {
+    let mut sh = SpatialHash::cube(1.).with_wrap(Some([10., 10.]));
+    sh.add(0.5, 0.5, 0);
+    sh.add(7.5, 0.5, 1);
+    // the cell at x = 0 is 1 away across the seam, the one at x = 7 is 2 away.
+    assert_eq!(sh.nearest_nonempty(9.5, 0.5), Some(([0.5, 0.5], &[0][..])));
+    assert_eq!(sh.nearest_nonempty(8.5, 0.5), Some(([7.5, 0.5], &[1][..])));
+}
+
+#[test]
+fn add_indexed_test() {
+    let mut sh = SpatialHash::hex(0.05);
+    for (i, [x, y]) in random_points(200, 60).into_iter().enumerate() {
+        let expected = sh.idx(x, y);
+        let (idx, key, vals) = sh.add_indexed(x, y, i);
+        assert_eq!((idx, key), expected);
+        assert_eq!(vals.last(), Some(&i));
+        assert!(sh
+            .bins()
+            .any(|(bi, bk, vals)| (bi, bk) == expected && vals.contains(&i)));
+    }
+}
+
+#[test]
+fn builder_test() {
+    use crate::hash::SimpleHashBuilder;
+    let kind = CoordinateKind::Hex {
+        circumradius: 0.05,
+        orientation: HexOrientation::PointyTop,
+    };
+    let mut built = SpatialHashBuilder::new()
+        .kind(kind)
+        .hasher(SimpleHashBuilder::default())
+        .build();
+    let mut manual = SpatialHash::new(kind).with_hasher(SimpleHashBuilder::default());
+    for (i, [x, y]) in random_points(300, 61).into_iter().enumerate() {
+        assert_eq!(built.idx(x, y), manual.idx(x, y));
+        built.add(x, y, i);
+        manual.add(x, y, i);
+    }
+    assert_eq!(
+        built.bins().collect::<Vec<_>>(),
+        manual.bins().collect::<Vec<_>>()
+    );
+    for [x, y] in random_points(50, 62) {
+        assert!(built.query_one_ring(x, y).eq(manual.query_one_ring(x, y)));
+    }
+
+    let small = SpatialHashBuilder::<u8>::default().buckets::<4>().build();
+    assert_eq!(small.kind, SpatialHash::<u8>::default().kind);
+    assert!(random_points(50, 63)
+        .into_iter()
+        .all(|[x, y]| small.idx(x, y).0 < 4));
+}
+
+#[test]
+fn try_idx_test() {
+    let sh = SpatialHash::<()>::hex(0.1);
+    assert_eq!(sh.try_idx(0.3, 0.4), Some(sh.idx(0.3, 0.4)));
+    for [x, y] in [
+        [f32::NAN, 0.],
+        [0., f32::NAN],
+        [f32::INFINITY, 0.],
+        [0., f32::NEG_INFINITY],
+    ] {
+        assert_eq!(sh.try_idx(x, y), None);
+    }
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "non-finite")]
+fn idx_non_finite_test() {
+    SpatialHash::<()>::cube(0.1).add(f32::NAN, 0., ());
+}
+
+#[test]
+fn merge_test() {
+    let kind = CoordinateKind::Tri { side_len: 0.05 };
+    let pts = random_points(400, 64);
+    let (left, right): (Vec<_>, Vec<_>) = pts.iter().enumerate().partition(|(_, p)| p[0] < 0.5);
+    let mut sh = SpatialHash::from_iter_with(kind, left.iter().map(|&(i, &[x, y])| (x, y, i)));
+    let mut other = SpatialHash::<usize, 16>::with_kind(kind)
+        .with_hasher(crate::hash::SimpleHashBuilder::default());
+    other.extend(right.iter().map(|&(i, &[x, y])| (x, y, i)));
+    // a cell shared by both
+    let [sx, sy] = *right[0].1;
+    sh.add(sx, sy, usize::MAX);
+
+    sh.merge(other);
+    assert_eq!(sh.len(), pts.len() + 1);
+    for (i, &[x, y]) in pts.iter().enumerate() {
+        assert!(sh.contains(x, y, |&j| j == i), "{i} missing");
+    }
+    assert_eq!(sh.query(sx, sy)[0], usize::MAX);
+}
+
+#[test]
+fn merge_across_wrap_test() {
+    let mut plain = SpatialHash::cube(1.);
+    plain.add(5.5, 0.5, 1);
+    let mut sh = SpatialHash::cube(1.).with_wrap(Some([4., 4.]));
+    sh.merge(plain.clone());
+    assert_eq!(sh.len(), 1);
+    assert_eq!(sh.query(5.5, 0.5), [1]);
+    assert_eq!(sh.query(1.5, 0.5), [1]);
+
+    let mut cloned = SpatialHash::cube(1.).with_wrap(Some([4., 4.]));
+    cloned.extend_from(&plain);
+    assert_eq!(cloned.query(1.5, 0.5), [1]);
+
+    // the plain cell at x = 5 is the same as the wrapped cell at x = 1.
+    assert_eq!(plain.difference(&sh).count(), 0);
+    let mut elsewhere = SpatialHash::cube(1.).with_wrap(Some([4., 4.]));
+    elsewhere.add(2.5, 0.5, 2);
+    assert_eq!(plain.difference(&elsewhere).count(), 1);
+}
+
+#[test]
+#[should_panic(expected = "matching kinds")]
+fn merge_kind_mismatch_test() {
+    let mut sh = SpatialHash::<()>::cube(0.1);
+    sh.merge(SpatialHash::<()>::hex(0.1));
+}
+
+#[test]
+fn density_grid_test() {
+    let mut sh = SpatialHash::cube(1.);
+    for (x, n) in [(0.5, 3), (1.5, 1), (-0.5, 2)] {
+        for _ in 0..n {
+            sh.add(x, 0.5, ());
+        }
+    }
+    assert_eq!(
+        sh.density_grid(),
+        vec![([-1, 0], 2), ([0, 0], 3), ([1, 0], 1)]
+    );
+
+    // every neighbor of the center gains one item, adding new cells where they were empty
+    sh.add_one_ring(0.5, 0.5, (), |_| {});
+    let grid = sh.density_grid();
+    assert_eq!(grid.len(), 9);
+    assert!(grid.windows(2).all(|w| w[0].0 < w[1].0));
+    for (key, count) in grid {
+        let expected = match key {
+            [-1, 0] => 3,
+            [0, 0] => 4,
+            [1, 0] => 2,
+            _ => 1,
+        };
+        assert_eq!(count, expected, "{key:?}");
+    }
+}
+
+#[test]
+fn hex_ring_test() {
+    let c = HexAxial { q: 3, r: -7 };
+    assert_eq!(HexAxial::ring(c, 0).collect::<Vec<_>>(), vec![c]);
+
+    let mut ring = c.ring(1).collect::<Vec<_>>();
+    let mut one_ring = c.one_ring().to_vec();
+    ring.sort();
+    one_ring.sort();
+    assert_eq!(ring, one_ring);
+
+    for n in 1..8 {
+        let ring = c.ring(n).collect::<Vec<_>>();
+        assert_eq!(ring.len(), 6 * n as usize);
+        assert!(ring.iter().all(|h| h.distance(&c) == n as i32));
+        // consecutive cells are neighbors, all the way around
+        for (i, h) in ring.iter().enumerate() {
+            assert_eq!(h.distance(&ring[(i + 1) % ring.len()]), 1);
+        }
+
+        let spiral = c.spiral(n).collect::<std::collections::BTreeSet<_>>();
+        let n = n as usize;
+        assert_eq!(spiral.len(), 1 + 3 * n * (n + 1));
+    }
+}
+
+#[test]
+fn tri_ring_test() {
+    use std::collections::BTreeSet;
+    let key = |c: &TriCoord<i32>| (c.s, c.t, c.u);
+    // one triangle of each orientation
+    let centers = [
+        TriCoord::from_canon2d([4, -3]),
+        TriCoord::from_canon2d([5, -3]),
+    ];
+    assert_ne!(centers[0].points_up(), centers[1].points_up());
+    for c in centers {
+        assert_eq!(TriCoord::ring(c, 0).collect::<Vec<_>>(), vec![c]);
+        let ring = c.ring(1).map(|c| key(&c)).collect::<BTreeSet<_>>();
+        let one_ring = c.one_ring().iter().map(key).collect::<BTreeSet<_>>();
+        assert_eq!(ring, one_ring);
+
+        // the same triangles as expanding one_ring outwards
+        let mut seen = BTreeSet::from([key(&c)]);
+        let mut frontier = vec![c];
+        for n in 1..6u32 {
+            frontier = frontier
+                .iter()
+                .flat_map(|f| f.one_ring())
+                .filter(|f| seen.insert(key(f)))
+                .collect();
+            let ring = c.ring(n).collect::<Vec<_>>();
+            assert_eq!(ring.len(), 12 * n as usize);
+            assert!(ring.iter().all(|t| matches!(t.s + t.t + t.u, 1 | 2)));
+            assert_eq!(
+                ring.iter().map(key).collect::<BTreeSet<_>>(),
+                frontier.iter().map(key).collect::<BTreeSet<_>>()
+            );
+            let n = n as usize;
+            assert_eq!(c.spiral(n as u32).count(), 1 + 6 * n * (n + 1));
+        }
+    }
+}
+
+#[test]
+fn deterministic_test() {
+    let build = || {
+        let mut sh = SpatialHash::deterministic(CoordinateKind::Hex {
+            circumradius: 0.05,
+            orientation: HexOrientation::PointyTop,
+        });
+        sh.extend(
+            random_points(500, 65)
+                .into_iter()
+                .map(|[x, y]| (x, y, [x, y])),
+        );
+        sh
+    };
+    let (a, b) = (build(), build());
+    assert_eq!(a.bins().collect::<Vec<_>>(), b.bins().collect::<Vec<_>>());
+    // fixed regardless of the process, unlike `RandomState`
+    assert_eq!(a.idx(0.5, 0.5), (255, [2, 7]));
+}
+
+#[test]
+fn potential_pairs_test() {
+    use std::collections::BTreeSet;
+    let pts = random_points(150, 66);
+    for kind in all_kinds(0.1) {
+        let sh =
+            SpatialHash::from_iter_with(kind, pts.iter().enumerate().map(|(i, &[x, y])| (x, y, i)));
+        let collect = |pairs: &mut dyn Iterator<Item = (&usize, &usize)>| {
+            let pairs = pairs
+                .map(|(&a, &b)| (a.min(b), a.max(b)))
+                .collect::<Vec<_>>();
+            let set = pairs.iter().copied().collect::<BTreeSet<_>>();
+            assert_eq!(set.len(), pairs.len(), "{kind:?} yielded a pair twice");
+            assert!(pairs.iter().all(|(a, b)| a != b));
+            set
+        };
+        let key = |i: usize| sh.idx(pts[i][0], pts[i][1]).1;
+        let brute = |adjacent: &dyn Fn([i32; 2], [i32; 2]) -> bool| {
+            (0..pts.len())
+                .flat_map(|i| (i + 1..pts.len()).map(move |j| (i, j)))
+                .filter(|&(i, j)| adjacent(key(i), key(j)))
+                .collect::<BTreeSet<_>>()
+        };
+
+        assert_eq!(collect(&mut sh.potential_pairs()), brute(&|a, b| a == b));
+        assert_eq!(
+            collect(&mut sh.potential_pairs_one_ring()),
+            brute(&|a, b| a == b || sh.neighbor_keys_of(a).any(|n| n == b))
+        );
+    }
+}
+
+#[test]
+fn cell_bounds_test() {
+    let root3: f32 = (3.0f32).sqrt();
+    let sizes = [[0.1, 0.1], [0.1 * root3, 0.2], [0.1, 0.05 * root3]];
+    let rect = CoordinateKind::Rect {
+        w: 0.1,
+        h: 0.03,
+        align: CellAlign::Corner,
+    };
+    for (kind, size) in all_kinds(0.1)
+        .into_iter()
+        .zip(sizes)
+        .chain([(rect, [0.1, 0.03])])
+    {
+        let sh = SpatialHash::<()>::new(kind);
+        for [x, y] in random_points(500, 67) {
+            let [x, y] = [x * 4. - 2., y * 4. - 2.];
+            let ([lx, ly], [hx, hy]) = sh.cell_bounds(x, y);
+            assert!(lx <= x && x <= hx && ly <= y && y <= hy, "{kind:?} {x} {y}");
+            // and the box is exactly the width and height of the cell
+            assert!((hx - lx - size[0]).abs() < 1e-5, "{kind:?} {}", hx - lx);
+            assert!((hy - ly - size[1]).abs() < 1e-5, "{kind:?} {}", hy - ly);
+        }
+    }
+
+    // cubes tile, with each cell's max corner exactly the min corner of the next
+    let sh = SpatialHash::<()>::cube(0.1);
+    for i in -20..20 {
+        for j in -20..20 {
+            let [x, y] = [i as f32 * 0.1 + 0.05, j as f32 * 0.1 + 0.05];
+            let (_, max) = sh.cell_bounds(x, y);
+            assert_eq!(sh.cell_bounds(x + 0.1, y).0[0], max[0]);
+            assert_eq!(sh.cell_bounds(x, y + 0.1).0[1], max[1]);
+        }
+    }
+}
+
+#[test]
+fn conflict_resolution_test() {
+    let mut sh = SpatialHash::cube(1.);
+    sh.add_with_conflict_resolution(0.5, 0.5, 1, |a, b| a + b);
+    sh.add_with_conflict_resolution(0.2, 0.7, 2, |a, b| a + b);
+    assert_eq!(sh.query(0.5, 0.5), &[3]);
+
+    // mixed with plain `add`, every item of the bin is folded into one.
+    sh.add(0.5, 0.5, 10);
+    sh.add(0.5, 0.5, 100);
+    assert_eq!(
+        sh.try_add_with_conflict_resolution(0.5, 0.5, 7, |a, b| a + b),
+        Err(7)
+    );
+    assert_eq!(sh.query(0.5, 0.5), &[3, 10, 100]);
+    sh.add_with_conflict_resolution(0.5, 0.5, 0, |a, b| a * 2 + b);
+    assert_eq!(sh.query(0.5, 0.5), &[((3 * 2) + 10) * 2 + 100]);
+
+    assert_eq!(
+        sh.try_add_with_conflict_resolution(0.5, 0.5, 1, |a, b| a + b),
+        Ok(())
+    );
+    assert_eq!(
+        sh.try_add_with_conflict_resolution(3.5, 0.5, 1, |a, b| a + b),
+        Ok(())
+    );
+    assert_eq!(sh.query(0.5, 0.5), &[133]);
+    assert_eq!(sh.query(3.5, 0.5), &[1]);
+}
+
+#[test]
+fn tracked_test() {
+    let pts = random_points(300, 69);
+    let mut sh = TrackedSpatialHash::tracked(CoordinateKind::Cube {
+        side_len: 0.1,
+        align: CellAlign::Corner,
+    });
+    for (i, &[x, y]) in pts.iter().enumerate() {
+        sh.add_tracked(x, y, i);
+    }
+    for (i, &p) in pts.iter().enumerate() {
+        assert!(sh.query_positions(p[0], p[1]).contains(&(p, i)));
+    }
+
+    let hex = CoordinateKind::Hex {
+        circumradius: 0.03,
+        orientation: HexOrientation::PointyTop,
+    };
+    // positions are kept as they are, even though items move to the old cell centers
+    let centered = sh.rebuild_as(hex);
+    let mut kept = centered
+        .iter()
+        .flat_map(|(_, vals)| vals.iter().map(|&(p, i)| (i, p)))
+        .collect::<Vec<_>>();
+    kept.sort_by_key(|&(i, _)| i);
+    assert_eq!(kept.len(), pts.len());
+    assert!(kept.iter().all(|&(i, p)| p == pts[i]));
+
+    // while rebuilding from the tracked positions puts each item back at its own position
+    let exact = sh.rebuild_tracked_as(hex);
+    sh.set_kind(hex);
+    for (i, &p) in pts.iter().enumerate() {
+        assert!(exact.query_positions(p[0], p[1]).contains(&(p, i)));
+        assert!(sh.query_positions(p[0], p[1]).contains(&(p, i)));
+    }
+    assert_eq!(exact.len(), pts.len());
+}
+
+#[test]
+fn remove_item_test() {
+    for kind in all_kinds(0.1) {
+        let mut sh = SpatialHash::new(kind);
+        sh.add(0.5, 0.5, 'b');
+        sh.add_one_ring(0.5, 0.5, 'a', |_| {});
+        sh.add(0.5, 0.5, 'a');
+        let cells = sh.bins().count();
+        assert_eq!(sh.len(), cells + 2);
+
+        assert_eq!(sh.remove_item(0.5, 0.5, &'a'), 2);
+        assert_eq!(sh.query(0.5, 0.5), &['b']);
+        // the remaining copies in each neighbor, after which only 'b' is left
+        assert_eq!(sh.remove_item_one_ring(0.5, 0.5, &'a'), cells - 1);
+        assert_eq!(sh.bins().count(), 1, "{kind:?}");
+        assert_eq!(sh.remove_item_one_ring(0.5, 0.5, &'a'), 0);
+        assert_eq!(sh.remove_item(0.5, 0.5, &'b'), 1);
+        assert!(sh.is_empty() && sh.load_factor() == 0.);
+    }
+}
+
+#[test]
+fn capacity_stats_test() {
+    let mut sh = SpatialHash::cube(0.05);
+    assert_eq!(sh.capacity_stats(), Default::default());
+    // a dense clump along with sparse points elsewhere
+    for (i, [x, y]) in random_points(1000, 71).into_iter().enumerate() {
+        if i % 2 == 0 {
+            sh.add(0.5 + x * 0.04, 0.5 + y * 0.04, 0u8);
+        } else {
+            sh.add(x, y, 1);
+        }
+    }
+    sh.entry(10., 10.);
+    sh.entry(-10., 10.);
+    sh.entry(-10., 10.);
+
+    let stats = sh.capacity_stats();
+    assert_eq!(stats.total_items, 1000);
+    assert_eq!(stats.empty_bins, 2);
+    assert_eq!(stats.occupied_bins, sh.bins().count());
+    assert!(
+        stats.max_bin_items as f32 > 4. * stats.mean_bin_items(),
+        "{stats:?}"
+    );
+    assert_eq!(
+        stats.max_bin_items,
+        sh.bins().map(|(_, _, v)| v.len()).max().unwrap()
+    );
+    assert!(stats.total_capacity >= stats.total_items);
+}
+
+#[test]
+fn add_line_wu_test() {
+    let mut sh = SpatialHash::cube(1.);
+    // from the center of cell (0,0) to the center of cell (4,1)
+    sh.add_line_wu([0.5, 0.5], [4.5, 1.5], 'l').unwrap();
+    let cells = sh.density_grid();
+    assert_eq!(
+        cells.iter().map(|&(k, _)| k).collect::<Vec<_>>(),
+        vec![
+            [0, 0],
+            [1, 0],
+            [1, 1],
+            [2, 0],
+            [2, 1],
+            [3, 0],
+            [3, 1],
+            [4, 1]
+        ]
+    );
+    assert!(cells.iter().all(|&(_, n)| n == 1));
+
+    let mut coverage = SpatialHash::cube(1.);
+    let mut total = 0.;
+    coverage
+        .add_line_wu_with([0.5, 0.5], [4.5, 1.5], |cov, bin| {
+            total += cov;
+            bin.push(cov);
+        })
+        .unwrap();
+    // one full cell's worth of coverage per column
+    assert!((total - 5.).abs() < 1e-5, "{total}");
+    assert_eq!(coverage.query(2.5, 0.5), &[0.5]);
+    assert_eq!(coverage.query(1.5, 1.5), &[0.25]);
+
+    // a line within a single cell only adds once
+    let mut short = SpatialHash::cube(1.);
+    short.add_line_wu([0.5, 0.5], [0.6, 0.5], ()).unwrap();
+    assert_eq!(short.len(), 1);
+}
+
+#[test]
+fn add_line_wu_rect_test() {
+    // the same line as in add_line_wu_test, stretched along with the grid.
+    let mut cube = SpatialHash::cube(1.);
+    assert!(cube.add_line_wu([0.5, 0.5], [4.5, 1.5], ()).is_some());
+    let mut rect = SpatialHash::new(CoordinateKind::Rect {
+        w: 2.,
+        h: 0.5,
+        align: CellAlign::Corner,
+    });
+    assert!(rect.add_line_wu([1., 0.25], [9., 0.75], ()).is_some());
+    assert_eq!(rect.density_grid(), cube.density_grid());
+
+    for kind in [
+        CoordinateKind::Hex {
+            circumradius: 1.,
+            orientation: HexOrientation::PointyTop,
+        },
+        CoordinateKind::Tri { side_len: 1. },
+    ] {
+        let mut sh = SpatialHash::new(kind);
+        assert!(sh.add_line_wu([0.5, 0.5], [4.5, 1.5], ()).is_none());
+        assert!(sh.is_empty());
+    }
+}
+
+#[test]
+fn query_segment_test() {
+    let segments = [
+        ([0.1, 0.2], [0.9, 0.7]),
+        ([0.8, 0.1], [0.15, 0.95]),
+        ([0.51, 0.05], [0.51, 0.95]),
+        ([0.3, 0.3], [0.31, 0.3]),
+    ];
+    for kind in all_kinds(0.05).into_iter().chain([CoordinateKind::Rect {
+        w: 0.05,
+        h: 0.02,
+        align: CellAlign::Corner,
+    }]) {
+        for (start, end) in segments {
+            let mut sh = SpatialHash::new(kind);
+            // one item on each of many points along the path, and one far from it
+            let on_path = (0..=200)
+                .map(|i| {
+                    let t = i as f32 / 200.;
+                    [0, 1].map(|j| start[j] + (end[j] - start[j]) * t)
+                })
+                .collect::<Vec<_>>();
+            for &[x, y] in &on_path {
+                sh.add(x, y, [x, y]);
+            }
+            sh.add(-0.5, 0.5, [-0.5, 0.5]);
+
+            let found = sh.query_segment(start, end).flatten().collect::<Vec<_>>();
+            for p in &on_path {
+                assert!(found.contains(&p), "{kind:?} missed {p:?}");
+            }
+            assert!(!found.contains(&&[-0.5, 0.5]));
+            // and each bin once
+            assert_eq!(found.len(), on_path.len(), "{kind:?}");
+        }
+    }
+}
+
+#[test]
+fn into_iter_test() {
+    for kind in all_kinds(0.1) {
+        let pts = random_points(400, 74);
+        let sh =
+            SpatialHash::from_iter_with(kind, pts.iter().enumerate().map(|(i, &[x, y])| (x, y, i)));
+        let positions = sh
+            .iter()
+            .flat_map(|(p, vals)| vals.iter().map(move |&i| (i, p)))
+            .collect::<std::collections::BTreeMap<_, _>>();
+        let mut count = 0;
+        for (p, i) in sh {
+            assert_eq!(positions[&i], p);
+            count += 1;
+        }
+        assert_eq!(count, pts.len());
+    }
+}
+
+#[test]
+fn query_with_neighbors_collected_test() {
+    let sh = SpatialHash::from_iter_with(
+        CoordinateKind::Tri { side_len: 0.05 },
+        random_points(1000, 75)
+            .into_iter()
+            .map(|[x, y]| (x, y, [x, y])),
+    );
+    for [x, y] in random_points(50, 76) {
+        let collected = sh.query_with_neighbors_collected(x, y);
+        let expected = sh.query_one_ring(x, y).map(<[_]>::len).sum::<usize>();
+        assert_eq!(collected.len(), expected);
+        // the center bin comes first
+        assert!(collected.starts_with(&sh.query(x, y).iter().collect::<Vec<_>>()));
+    }
+}
+
+#[test]
+fn fold_multiply_shift_test() {
+    fn check<const N: usize>() {
+        let hashes = (0..1000u64).map(|i| i.wrapping_mul(0x9E3779B97F4A7C15));
+        let mut seen = vec![0; N];
+        for h in hashes.chain([0, u64::MAX]) {
+            let i = Fold::MultiplyShift.apply::<N>(h);
+            assert!(i < N, "{i} out of 0..{N}");
+            seen[i] += 1;
+        }
+        assert!(seen.iter().filter(|&&c| c > 0).count() * 2 > N.min(1000));
+    }
+    check::<1>();
+    check::<7>();
+    check::<251>();
+    check::<256>();
+    check::<1024>();
+
+    let pts = random_points(500, 76);
+    let sh = SpatialHash::from_iter_with(
+        CoordinateKind::Hex {
+            circumradius: 0.05,
+            orientation: HexOrientation::PointyTop,
+        },
+        pts.iter().enumerate().map(|(i, &[x, y])| (x, y, i)),
+    )
+    .resize_buckets::<251>()
+    .with_fold(Fold::MultiplyShift);
+    assert_eq!(sh.fold(), Fold::MultiplyShift);
+    for (i, &[x, y]) in pts.iter().enumerate() {
+        assert!(sh.contains(x, y, |&j| j == i));
+    }
+    let built = SpatialHashBuilder::<()>::new()
+        .fold(Fold::MultiplyShift)
+        .build();
+    assert_eq!(built.fold(), Fold::MultiplyShift);
+}
+
+#[test]
+fn for_each_cell_in_radius_test() {
+    let pts = random_points(1500, 77);
+    for kind in all_kinds(0.05) {
+        let sh = SpatialHash::from_iter_with(kind, pts.iter().map(|&[x, y]| (x, y, [x, y])));
+        for q in random_points(20, 78) {
+            for rad in [0.02, 0.1, 0.25] {
+                let mut cells = vec![];
+                sh.for_each_cell_in_radius(q[0], q[1], rad, |vals| {
+                    assert!(!vals.is_empty());
+                    cells.push(sh.idx(vals[0][0], vals[0][1]).1);
+                });
+                let n = cells.len();
+                cells.sort();
+                cells.dedup();
+                assert_eq!(cells.len(), n, "{kind:?} visited a cell twice");
+                for p in pts.iter().filter(|&&p| dist(p, q) <= rad) {
+                    assert!(
+                        cells.contains(&sh.idx(p[0], p[1]).1),
+                        "{kind:?} missed {p:?}"
+                    );
+                }
+            }
+        }
+        let mut all = 0;
+        sh.for_each_cell_in_radius(0.5, 0.5, f32::INFINITY, |vals| all += vals.len());
+        assert_eq!(all, pts.len());
+        let sorted = sh.query_radius_sorted(0.5, 0.5, f32::INFINITY, |&p| p);
+        assert_eq!(sorted.len(), pts.len());
+        sh.for_each_cell_in_radius(0.5, 0.5, f32::NAN, |_| panic!("NaN radius"));
+    }
+}
+
+#[test]
+fn flat_top_hex_test() {
+    let root3: f32 = (3.0f32).sqrt();
+    let (pointy, flat) = (HexOrientation::PointyTop, HexOrientation::FlatTop);
+    // the center of flat top (2, -1) is at x = 1.5 * q, y = root3 * (r + q / 2), which is
+    // closer to the center of pointy top (2, 0) than that of any other pointy top hex.
+    for (o, key) in [(pointy, [2, 0]), (flat, [2, -1])] {
+        let h = HexAxial::from_euclidean_oriented(3., 0., 1., o);
+        assert_eq!([h.q, h.r], key);
+        let kind = CoordinateKind::Hex {
+            circumradius: 1.,
+            orientation: o,
+        };
+        assert_eq!(SpatialHash::<()>::new(kind).idx(3., 0.).1, key);
+    }
+    let c = HexAxial { q: 1, r: 0 }.to_euclidean_oriented(1., flat);
+    assert!(dist(c, [1.5, root3 / 2.]) < 1e-5);
+
+    // every point is inside the flat top hexagon of its cell, and nearest to its center.
+    let r = 0.05;
+    let apothem = r * root3 / 2.;
+    let kind = CoordinateKind::Hex {
+        circumradius: r,
+        orientation: flat,
+    };
+    for p in random_points(2000, 90) {
+        let h = HexAxial::from_euclidean_oriented(p[0], p[1], r, flat);
+        let c = h.to_euclidean_oriented(r, flat);
+        let d = [p[0] - c[0], p[1] - c[1]];
+        for [nx, ny] in [[0., 1.], [root3 / 2., 0.5], [root3 / 2., -0.5]] {
+            assert!((d[0] * nx + d[1] * ny).abs() <= apothem + 1e-5, "{p:?}");
+        }
+        for n in h.one_ring() {
+            assert!(dist(p, c) <= dist(p, n.to_euclidean_oriented(r, flat)) + 1e-5);
+        }
+        let (lo, hi) = kind.bounds([h.q, h.r]);
+        assert!(lo[0] <= p[0] && p[0] <= hi[0] && lo[1] <= p[1] && p[1] <= hi[1]);
+        assert!((hi[0] - lo[0] - 2. * r).abs() < 1e-5);
+    }
+
+    // clipped one ring queries find every item within the clearance of the query point.
+    let pts = random_points(3000, 91);
+    let sh = SpatialHash::from_iter_with(kind, pts.iter().map(|&[x, y]| (x, y, [x, y])));
+    let radius = kind.ring_clearance(1);
+    for q in random_points(200, 92) {
+        let found = sh.query_one_ring_items(q[0], q[1]).collect::<Vec<_>>();
+        for p in pts.iter().filter(|&&p| dist(p, q) <= radius) {
+            assert!(found.contains(&p), "{q:?} {p:?}");
+        }
+    }
+}
+
+#[test]
+fn clear_region_test() {
+    let pts = random_points(3000, 93);
+    let (min, max) = ([0.2, 0.3], [0.6, 0.5]);
+    for kind in [
+        CoordinateKind::Cube {
+            side_len: 0.05,
+            align: CellAlign::Corner,
+        },
+        CoordinateKind::Rect {
+            w: 0.05,
+            h: 0.02,
+            align: CellAlign::Corner,
+        },
+        CoordinateKind::Hex {
+            circumradius: 0.03,
+            orientation: HexOrientation::PointyTop,
+        },
+        CoordinateKind::Tri { side_len: 0.05 },
+    ] {
+        let mut sh = SpatialHash::from_iter_with(kind, pts.iter().map(|&[x, y]| (x, y, [x, y])));
+        let bins_before = sh.data.iter().map(|b| b.len()).sum::<usize>();
+
+        let [x, y] = pts[0];
+        let in_bin = sh.query(x, y).len();
+        sh.clear_bin(x, y);
+        assert!(sh.query(x, y).is_empty());
+        assert_eq!(sh.len(), pts.len() - in_bin);
+        let bins = sh.data.iter().map(|b| b.len()).sum::<usize>();
+        assert_eq!(bins, bins_before - 1);
+
+        sh.clear_region(min, max);
+        let inside = |[x, y]: [f32; 2]| min[0] <= x && x <= max[0] && min[1] <= y && y <= max[1];
+        let left = sh
+            .iter()
+            .flat_map(|(_, ps)| ps.to_vec())
+            .collect::<Vec<_>>();
+        assert!(left.iter().all(|&p| !inside(p)), "{kind:?}");
+        // everything far enough from the rectangle and the first cleared bin survives.
+        let margin = 2. * kind.ring_clearance(1).max(0.05);
+        let far = |[x, y]: [f32; 2]| {
+            x < min[0] - margin || x > max[0] + margin || y < min[1] - margin || y > max[1] + margin
+        };
+        for &p in pts[1..]
+            .iter()
+            .filter(|&&p| far(p) && dist(p, pts[0]) > margin)
+        {
+            assert!(left.contains(&p), "{kind:?} {p:?}");
+        }
+        assert!(sh.data.iter().all(|b| b.values().all(|v| !v.is_empty())));
+    }
+}
+
+#[test]
+fn reserve_test() {
+    let pts = random_points(2000, 94);
+    let kind = CoordinateKind::Cube {
+        side_len: 0.02,
+        align: CellAlign::Corner,
+    };
+    let items = || pts.iter().enumerate().map(|(i, &[x, y])| (x, y, i));
+    let plain = SpatialHash::from_iter_with(kind, items());
+
+    let mut tree = SpatialHash::new(kind);
+    tree.reserve(5000);
+    tree.extend(items());
+    let mut flat = FlatSpatialHash::flat(kind);
+    flat.reserve(5000);
+    assert!(flat.data[0].capacity() >= 5000);
+    flat.extend(items());
+
+    for [x, y] in random_points(200, 95) {
+        let sorted = |mut v: Vec<usize>| {
+            v.sort();
+            v
+        };
+        let expected = sorted(plain.query_one_ring_items(x, y).copied().collect());
+        assert_eq!(
+            sorted(tree.query_one_ring_items(x, y).copied().collect()),
+            expected
+        );
+        assert_eq!(
+            sorted(flat.query_one_ring_items(x, y).copied().collect()),
+            expected
+        );
+    }
+}
+
+#[test]
+fn center_aligned_cells_test() {
+    let l = 0.25;
+    let center = CoordinateKind::Cube {
+        side_len: l,
+        align: CellAlign::Center,
+    };
+    let corner = CoordinateKind::Cube {
+        side_len: l,
+        align: CellAlign::Corner,
+    };
+    let sh = SpatialHash::<()>::new(center);
+    let [x, y] = [0.5, -0.75];
+    // every point near a lattice point is in the one cell centered there.
+    let e = 1e-3;
+    for [dx, dy] in [[0., 0.], [e, e], [-e, e], [e, -e], [-e, -e]] {
+        assert_eq!(center.key(x + dx, y + dy), [2, -3]);
+    }
+    let corners = [[e, e], [-e, e], [e, -e], [-e, -e]].map(|[dx, dy]| corner.key(x + dx, y + dy));
+    assert_eq!(
+        corners
+            .iter()
+            .collect::<std::collections::BTreeSet<_>>()
+            .len(),
+        4
+    );
+
+    assert_eq!(sh.nearest_centroid(x, y), [x, y]);
+    let (lo, hi) = sh.cell_bounds(x, y);
+    assert_eq!(
+        (lo, hi),
+        ([x - l / 2., y - l / 2.], [x + l / 2., y + l / 2.])
+    );
+    let h = Euclidean::from_euclidean_aligned(x, y, l, l, CellAlign::Center);
+    assert_eq!(h.to_euclidean_aligned(l, l, CellAlign::Center), lo);
+
+    let rect = CoordinateKind::Rect {
+        w: 0.2,
+        h: 0.1,
+        align: CellAlign::Center,
+    };
+    let pts = random_points(3000, 96);
+    for kind in [center, rect] {
+        let sh = SpatialHash::from_iter_with(kind, pts.iter().map(|&[x, y]| (x, y, [x, y])));
+        let radius = kind.ring_clearance(1);
+        for q in random_points(200, 97) {
+            let (lo, hi) = sh.cell_bounds(q[0], q[1]);
+            assert!(lo[0] <= q[0] && q[0] < hi[0] && lo[1] <= q[1] && q[1] < hi[1]);
+            let found = sh.query_one_ring_items(q[0], q[1]).collect::<Vec<_>>();
+            for p in pts.iter().filter(|&&p| dist(p, q) <= radius) {
+                assert!(found.contains(&p), "{kind:?} {q:?} {p:?}");
+            }
+        }
+        // segments see the same cells as sampling densely along them.
+        let (s, t) = ([0.11, 0.93], [0.87, 0.05]);
+        let along = sh.query_segment(s, t).flatten().count();
+        let mut sampled = (0..=2000)
+            .map(|i| i as f32 / 2000.)
+            .map(|f| kind.key(s[0] + (t[0] - s[0]) * f, s[1] + (t[1] - s[1]) * f))
+            .collect::<Vec<_>>();
+        sampled.dedup();
+        let expected = sampled
+            .into_iter()
+            .filter_map(|key| sh.bin(key))
+            .map(|b| b.len())
+            .sum::<usize>();
+        assert_eq!(along, expected, "{kind:?}");
+    }
+}
+
+#[test]
+fn query_radius_sorted_test() {
+    let mut pts = random_points(2000, 98);
+    // items at the same distance, in both orders of insertion.
+    pts.extend([
+        [0.75, 0.5],
+        [0.25, 0.5],
+        [0.5, 0.75],
+        [0.5, 0.25],
+        [0.75, 0.5],
+    ]);
+    for kind in [
+        CoordinateKind::Cube {
+            side_len: 0.05,
+            align: CellAlign::Corner,
+        },
+        CoordinateKind::Hex {
+            circumradius: 0.04,
+            orientation: HexOrientation::FlatTop,
+        },
+        CoordinateKind::Tri { side_len: 0.05 },
+    ] {
+        let sh = SpatialHash::from_iter_with(kind, pts.iter().map(|&[x, y]| (x, y, [x, y])));
+        for (q, rad) in [([0.5, 0.5], 0.1), ([0.13, 0.77], 0.03), ([0.9, 0.2], 0.25)] {
+            let found = sh.query_radius_sorted(q[0], q[1], rad, |&p| p);
+            let ds = found.iter().map(|&&p| dist(p, q)).collect::<Vec<_>>();
+            assert!(ds.windows(2).all(|w| w[0] <= w[1]), "{kind:?} {q:?}");
+            let mut expected = pts
+                .iter()
+                .filter(|&&p| dist(p, q) <= rad)
+                .collect::<Vec<_>>();
+            let mut got = found.clone();
+            let cmp = |a: &&[f32; 2], b: &&[f32; 2]| a.partial_cmp(b).unwrap();
+            expected.sort_by(cmp);
+            got.sort_by(cmp);
+            assert_eq!(got, expected, "{kind:?} {q:?}");
+            // the same query gives the same order every time.
+            assert_eq!(found, sh.query_radius_sorted(q[0], q[1], rad, |&p| p));
+        }
+        // these are exactly a quarter away, so they are only ordered by position.
+        let ties = sh
+            .query_radius_sorted(0.5, 0.5, 0.3, |&p| p)
+            .into_iter()
+            .filter(|&&p| dist(p, [0.5, 0.5]) == 0.25)
+            .copied()
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ties,
+            [
+                [0.25, 0.5],
+                [0.5, 0.25],
+                [0.5, 0.75],
+                [0.75, 0.5],
+                [0.75, 0.5]
+            ]
+        );
+    }
+}
+
+#[test]
+fn from_grid_test() {
+    let cols = (0..16)
+        .map(|i| (0..16).map(|j| i * 16 + j).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let values = cols.iter().map(Vec::as_slice).collect::<Vec<_>>();
+    let kind = CoordinateKind::Cube {
+        side_len: 0.1,
+        align: CellAlign::Corner,
+    };
+    // nodes are at the centers of the cells.
+    let sh = SpatialHash::from_grid(kind, [0.05, -0.75], [0.1, 0.1], &values);
+    assert_eq!(sh.len(), 256);
+    for [i, j] in [[0, 0], [3, 9], [15, 15], [15, 0]] {
+        let [x, y] = [0.05 + i as f32 * 0.1, -0.75 + j as f32 * 0.1];
+        assert_eq!(sh.query(x, y), &[i * 16 + j]);
+    }
+    let empty: [&[u8]; 0] = [];
+    assert!(SpatialHash::from_grid(kind, [0.; 2], [1.; 2], &empty).is_empty());
+}
+
+#[test]
+fn simple_hash_is_portable_test() {
+    use crate::hash::SimpleHash;
+    use std::hash::Hasher;
+    let finish = |f: &dyn Fn(&mut SimpleHash)| {
+        let mut h = SimpleHash::default();
+        f(&mut h);
+        h.finish()
+    };
+    let keys = finish(&|h| {
+        for v in [0, 1, -1, i32::MAX, i32::MIN, 12345] {
+            h.write_i32(v);
+        }
+    });
+    assert_eq!(keys, 0x17ef_7385_7fd3_1cf5);
+    // integers of any width are hashed by value, and bytes as little endian words.
+    assert_eq!(finish(&|h| h.write_usize(7)), finish(&|h| h.write_u64(7)));
+    assert_eq!(
+        finish(&|h| h.write_u16(0x0201)),
+        finish(&|h| h.write(&[1, 2]))
+    );
+    assert_eq!(finish(&|h| h.write(b"spatial hash")), 0x6a90_7476_034b_efea);
+}
+
+#[test]
+fn neighbor_keys_test() {
+    for (kind, n) in all_kinds(0.1).into_iter().zip([8, 6, 12]) {
+        let sh = SpatialHash::<()>::new(kind);
+        for [x, y] in random_points(50, 99) {
+            let center = sh.idx(x, y).1;
+            let keys = sh.neighbor_keys(x, y).collect::<Vec<_>>();
+            assert_eq!(keys.len(), n, "{kind:?}");
+            let distinct = keys.iter().collect::<std::collections::BTreeSet<_>>();
+            assert_eq!(distinct.len(), n);
+            assert!(keys.iter().all(|&k| k != center));
+            // neighboring is symmetric.
+            for k in keys {
+                let [cx, cy] = kind.centroid(k);
+                assert!(sh.neighbor_keys(cx, cy).any(|k| k == center));
+            }
+        }
+    }
+}
+
+#[test]
+#[should_panic(expected = "at least one bucket")]
+fn zero_buckets_test() {
+    let _ = SpatialHash::<(), 0>::with_cube(0.1);
+}
+
+#[test]
+#[should_panic(expected = "at least one bucket")]
+fn zero_buckets_3d_test() {
+    let _ = SpatialHash3::<(), 0>::with_kind(CoordinateKind3::Cube { side_len: 0.1 });
+}
+
+#[test]
+#[should_panic(expected = "at least one bucket")]
+fn zero_buckets_resize_test() {
+    let _ = SpatialHash::<()>::cube(0.1).resize_buckets::<0>();
+}
+
+#[test]
+fn wrap_test() {
+    let kind = CoordinateKind::Cube {
+        side_len: 0.1,
+        align: CellAlign::Corner,
+    };
+    let e = 1e-3;
+    let mut sh = SpatialHash::new(kind).with_wrap(Some([1., 2.]));
+    assert_eq!(sh.wrap(), Some([1., 2.]));
+    sh.add(1. - e, 0.5, 0);
+    sh.add(0.5, 2. - e, 1);
+    // points outside of the world are binned as if moved back inside.
+    sh.add(1.05, -0.05, 2);
+    assert_eq!(sh.idx(1.05, -0.05).1, [0, 19]);
+    assert_eq!(sh.query(0.05, 1.95), &[2]);
+
+    let near =
+        |sh: &SpatialHash<i32>, x, y| sh.query_one_ring_items(x, y).copied().collect::<Vec<_>>();
+    assert_eq!(near(&sh, 0., 0.5), [0]);
+    assert_eq!(near(&sh, 0.5, 0.), [1]);
+    assert!(near(&sh, 0.99, 0.01).contains(&2));
+    assert!(sh.neighbor_keys(0.05, 0.05).any(|k| k == [9, 19]));
+    let across = sh.query_rect([0.95, 0.45], [1.05, 0.55]).flatten().count();
+    assert_eq!(across, 1);
+    assert_eq!(sh.query_rect([-3., -3.], [3., 3.]).flatten().count(), 3);
+
+    // without wrapping, the items stay in the cells they were moved into.
+    let sh = sh.with_wrap(None);
+    assert!(near(&sh, 0., 0.5).is_empty());
+    assert_eq!(sh.query(0.05, 1.95), &[2]);
+
+    // adding the wrap afterwards moves items outside of the world.
+    let mut sh = SpatialHash::new(kind);
+    sh.add(-0.05, 0.5, 3);
+    let sh = sh.with_wrap(Some([1., 1.]));
+    assert_eq!(sh.query(0.95, 0.5), &[3]);
+    assert_eq!(sh.len(), 1);
+}
+
+#[test]
+#[should_panic(expected = "whole number of at least 3 cells")]
+fn wrap_partial_cells_test() {
+    let _ = SpatialHash::<()>::cube(0.3).with_wrap(Some([1., 1.]));
+}
+
+#[test]
+fn wrap_kind_change_test() {
+    let mut sh = SpatialHash::cube(0.1).with_wrap(Some([1., 1.]));
+    sh.add(0.05, 0.05, 0);
+    // the kind is public, so it may be changed without validating the wrap again.
+    sh.kind = CoordinateKind::Tri { side_len: 0.1 };
+    let _ = sh.query(0.5, 0.5);
+    sh.kind = CoordinateKind::Cube {
+        side_len: 0.1,
+        align: CellAlign::Corner,
+    };
+
+    // the number of cells across is recomputed for the new kind.
+    sh.rebuild_from(
+        CoordinateKind::Cube {
+            side_len: 0.25,
+            align: CellAlign::Corner,
+        },
+        |_| [1.1, 0.1],
+    );
+    assert_eq!(sh.wrap(), Some([1., 1.]));
+    assert_eq!(sh.idx(1.1, 0.1).1, [0, 0]);
+    assert!(sh.neighbor_keys(0.1, 0.1).any(|k| k == [3, 3]));
+}
+
+#[test]
+#[should_panic(expected = "only cubes and rectangles can wrap")]
+fn wrap_rebuild_tri_test() {
+    let mut sh = SpatialHash::<()>::cube(0.1).with_wrap(Some([1., 1.]));
+    sh.rebuild_from(CoordinateKind::Tri { side_len: 0.1 }, |_| [0., 0.]);
+}
+
+#[test]
+fn map_test() {
+    let pts = random_points(500, 100);
+    let mut sh = SpatialHash::<u32>::new(CoordinateKind::Tri { side_len: 0.05 })
+        .with_fold(Fold::MultiplyShift);
+    for (i, &[x, y]) in pts.iter().enumerate() {
+        sh.add(x, y, i as u32);
+    }
+    let idx = sh.idx(0.5, 0.5);
+    let before = sh
+        .bins()
+        .map(|(idx, key, vals)| (idx, key, vals.len()))
+        .collect::<Vec<_>>();
+    let mut calls = 0;
+    let mapped = sh.map(|i| {
+        calls += 1;
+        format!("item {i}")
+    });
+    assert_eq!(calls, pts.len());
+    assert_eq!(mapped.fold(), Fold::MultiplyShift);
+    let after = mapped
+        .bins()
+        .map(|(idx, key, vals)| (idx, key, vals.len()))
+        .collect::<Vec<_>>();
+    assert_eq!(before, after);
+    for (i, &[x, y]) in pts.iter().enumerate() {
+        assert!(mapped.query(x, y).contains(&format!("item {i}")));
+    }
+    // the randomly seeded hasher state carries over.
+    assert_eq!(mapped.idx(0.5, 0.5), idx);
+}
+
+#[test]
+fn map_keeps_backing_test() {
+    let mut flat = FlatSpatialHash::flat(CoordinateKind::Rect {
+        w: 0.1,
+        h: 0.2,
+        align: CellAlign::Center,
+    });
+    let pts = random_points(200, 103);
+    for (i, &[x, y]) in pts.iter().enumerate() {
+        flat.add(x, y, i);
+    }
+    let bins = flat.bins().count();
+    let mapped: FlatSpatialHash<String> = flat.map(|i| i.to_string());
+    assert_eq!(mapped.data[0].len(), bins);
+    for (i, &[x, y]) in pts.iter().enumerate() {
+        assert!(mapped.query(x, y).contains(&i.to_string()));
+    }
+}
+
+#[test]
+fn retain_map_keeps_backing_test() {
+    let pts = random_points(200, 104);
+    let mut flat = FlatSpatialHash::flat(CoordinateKind::Tri { side_len: 0.05 });
+    for (i, &[x, y]) in pts.iter().enumerate() {
+        flat.add(x, y, i);
+    }
+    let odd: FlatSpatialHash<String> = flat.retain_map(|i| (i % 2 == 1).then(|| i.to_string()));
+    assert_eq!(odd.len(), pts.len() / 2);
+    assert!(odd.data[0].values().all(|v| !v.is_empty()));
+    for (i, &[x, y]) in pts.iter().enumerate() {
+        assert_eq!(odd.query(x, y).contains(&i.to_string()), i % 2 == 1);
+    }
+}
+
+#[test]
+fn retain_map_test() {
+    let pts = random_points(1000, 101);
+    let sh = SpatialHash::from_iter_with(
+        CoordinateKind::Hex {
+            circumradius: 0.03,
+            orientation: HexOrientation::PointyTop,
+        },
+        pts.iter().enumerate().map(|(i, &[x, y])| (x, y, i)),
+    );
+    let bins = sh.bins().count();
+    let odd = sh.retain_map(|i| (i % 2 == 1).then(|| -(i as i64)));
+    assert_eq!(odd.len(), pts.len() / 2);
+    for (i, &[x, y]) in pts.iter().enumerate() {
+        let q = odd.query(x, y);
+        assert_eq!(q.contains(&-(i as i64)), i % 2 == 1);
+        assert!(q.iter().all(|v| v % 2 != 0));
+    }
+    // bins holding only even items are pruned rather than left empty.
+    assert!(odd.bins().count() < bins);
+    assert!(odd.data.iter().all(|b| b.values().all(|v| !v.is_empty())));
+    assert!(odd
+        .retain_map(|_| None::<()>)
+        .data
+        .iter()
+        .all(|b| b.is_empty()));
+}
+
+#[test]
+fn single_bucket_matches_generic_test() {
+    let pts = random_points(2000, 102);
+    for kind in [
+        CoordinateKind::Cube {
+            side_len: 0.05,
+            align: CellAlign::Center,
+        },
+        CoordinateKind::Hex {
+            circumradius: 0.04,
+            orientation: HexOrientation::FlatTop,
+        },
+        CoordinateKind::Tri { side_len: 0.05 },
+    ] {
+        let mut single = SpatialHash::<usize, 1>::with_kind(kind);
+        let mut generic = SpatialHash::<usize, 64>::with_kind(kind);
+        for (i, &[x, y]) in pts.iter().enumerate() {
+            single.add(x, y, i);
+            generic.add(x, y, i);
+        }
+        assert_eq!(single.data.len(), 1);
+        for [x, y] in random_points(200, 103) {
+            assert_eq!(single.idx(x, y).0, 0);
+            assert_eq!(single.query(x, y), generic.query(x, y));
+            let ring = |it: Vec<&[usize]>| it.concat();
+            assert_eq!(
+                ring(single.query_one_ring(x, y).collect()),
+                ring(generic.query_one_ring(x, y).collect()),
+                "{kind:?}"
+            );
+        }
+    }
+}
+
+#[test]
+fn query_many_test() {
+    let pts = random_points(3000, 104);
+    let sh = SpatialHash::from_iter_with(
+        CoordinateKind::Tri { side_len: 0.03 },
+        pts.iter().enumerate().map(|(i, &[x, y])| (x, y, i)),
+    );
+    let mut queries = random_points(500, 105);
+    queries.extend_from_slice(&pts[..500]);
+    let many = sh.query_many(&queries).collect::<Vec<_>>();
+    assert_eq!(many.len(), queries.len());
+    for (bin, &[x, y]) in many.into_iter().zip(&queries) {
+        assert_eq!(bin, sh.query(x, y));
+    }
+    assert_eq!(sh.query_many(&[]).count(), 0);
+}
+
+#[test]
+fn tri_vertices_test() {
+    let l = 0.2;
+    let cross = |o: [f32; 2], a: [f32; 2], b: [f32; 2]| {
+        (a[0] - o[0]) * (b[1] - o[1]) - (a[1] - o[1]) * (b[0] - o[0])
+    };
+    let close = |a: [f32; 2], b: [f32; 2]| dist(a, b) < 1e-5;
+    for [x, y] in random_points(200, 106) {
+        let tri = TriCoord::from_euclidean(x, y, l);
+        let vs = tri.vertices(l);
+        let c = tri.centroid(l);
+        assert_eq!(TriCoord::from_euclidean(c[0], c[1], l), tri);
+        // the centroid and (x,y) are on the same side of every edge.
+        for i in 0..3 {
+            let (a, b) = (vs[i], vs[(i + 1) % 3]);
+            assert!(cross(a, b, c) * cross(a, b, [x, y]) >= -1e-6, "{tri:?}");
+        }
+        assert_eq!(vs[0][1] > c[1], tri.points_up());
+        for side in vs.windows(2).map(|w| dist(w[0], w[1])) {
+            assert!((side - l).abs() < 1e-5);
+        }
+        // neighbors across an edge point the other way and share two corners.
+        let mut edge_neighbors = 0;
+        for n in tri.one_ring() {
+            let ns = n.vertices(l);
+            let shared = ns
+                .iter()
+                .filter(|&&v| vs.iter().any(|&w| close(v, w)))
+                .count();
+            if shared == 2 {
+                assert_ne!(n.points_up(), tri.points_up());
+                edge_neighbors += 1;
+            }
+        }
+        assert_eq!(edge_neighbors, 3);
+    }
+}
+
+#[test]
+fn hex_pixel_origin_test() {
+    use crate::coordinates::{hex_to_pixel, pixel_to_hex};
+    let r = 0.5;
+    for origin in [[0., 0.], [3.25, -1.5], [-100., 42.], [0.1, 0.2]] {
+        for q in -3..=3 {
+            for s in -3..=3 {
+                let hex = HexAxial { q, r: s };
+                let p = hex_to_pixel(hex, origin, r);
+                assert_eq!(pixel_to_hex(p, origin, r), hex, "{origin:?}");
+            }
+        }
+        assert_eq!(hex_to_pixel(HexAxial { q: 0, r: 0 }, origin, r), origin);
+        for [x, y] in random_points(100, 107) {
+            let p = [origin[0] + (x - 0.5) * 8., origin[1] + (y - 0.5) * 8.];
+            let hex = pixel_to_hex(p, origin, r);
+            let c = hex_to_pixel(hex, origin, r);
+            assert!(dist(c, p) <= r + 1e-4, "{origin:?} {p:?}");
+            assert_eq!(pixel_to_hex(c, origin, r), hex);
+        }
+    }
+}
+
+#[test]
+fn query_keyed_test() {
+    let pts = random_points(1000, 108);
+    let sh = SpatialHash::from_iter_with(
+        CoordinateKind::Rect {
+            w: 0.04,
+            h: 0.02,
+            align: CellAlign::Corner,
+        },
+        pts.iter().enumerate().map(|(i, &[x, y])| (x, y, i)),
+    );
+    for [x, y] in random_points(300, 109)
+        .into_iter()
+        .chain(pts[..100].iter().copied())
+    {
+        let (key, vals) = sh.query_keyed(x, y);
+        assert_eq!(key, sh.idx(x, y).1);
+        assert_eq!(vals, sh.query(x, y));
+    }
+    assert_eq!(sh.query_keyed(-5., -5.), ([-125, -250], &[][..]));
+}
+
+#[test]
+fn one_ring_world_bounds_test() {
+    let pts = random_points(500, 31);
+    let mut sh = SpatialHash::cube(0.1);
+    for &[x, y] in &pts {
+        sh.add(x, y, [x, y]);
+    }
+    // none of the neighbors of the center of a cell are clipped.
+    assert_eq!(sh.query_one_ring_keys(0.05, 0.05).count(), 9);
+
+    let bounded = sh.clone().with_world_bounds(Some([1., 1.]));
+    assert_eq!(bounded.world_bounds(), Some([1., 1.]));
+    let keys = bounded.query_one_ring_keys(0.05, 0.05).collect::<Vec<_>>();
+    assert_eq!(keys[0], [0, 0]);
+    let mut sorted = keys.clone();
+    sorted.sort();
+    assert_eq!(sorted, [[0, 0], [0, 1], [1, 0], [1, 1]]);
+
+    // exactly the neighbors outside of the 10 by 10 cells of the world are skipped.
+    let in_world = |k: &[i32; 2]| k.iter().all(|c| (0..10).contains(c));
+    let mut skipped = 0;
+    for &[x, y] in &pts {
+        let all = sh.query_one_ring_keys(x, y).collect::<Vec<_>>();
+        let kept = bounded.query_one_ring_keys(x, y).collect::<Vec<_>>();
+        let expected = all.iter().copied().filter(in_world).collect::<Vec<_>>();
+        assert_eq!(kept, expected);
+        skipped += all.len() - kept.len();
+    }
+    assert!(skipped > 0);
+
+    // the same through the builder.
+    let built = SpatialHashBuilder::<()>::new()
+        .kind(sh.kind)
+        .world_bounds([1., 1.])
+        .build();
+    assert_eq!(built.world_bounds(), Some([1., 1.]));
+    assert_eq!(built.query_one_ring_keys(0.05, 0.05).count(), 4);
 }